@@ -0,0 +1,17 @@
+#!/usr/bin/env bash
+# Build the contract for Wasm without std, once per contract hasher.
+#
+# `slushie/lib.rs` denies `unused_crate_dependencies`, so this also fails if
+# a hasher feature pulls in a crate its build doesn't use.
+# ink! 3's allocator uses nightly features, `RUSTC_BOOTSTRAP=1` lets a stable
+# toolchain build it. Needs `rustup target add wasm32-unknown-unknown`.
+set -euo pipefail
+
+cd "$(dirname "$0")/.."
+
+for hasher in contract-hasher-poseidon contract-hasher-blake; do
+    echo "checking slushie for wasm32-unknown-unknown with $hasher"
+    RUSTC_BOOTSTRAP=1 cargo check -p slushie \
+        --no-default-features --features "$hasher" \
+        --target wasm32-unknown-unknown
+done
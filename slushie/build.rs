@@ -0,0 +1,92 @@
+//! Generates the `ZEROS` tables of the merkle tree hashers
+//!
+//! Every table is derived from `ZEROS_SEED`: the zero leaf is the hash of the seed
+//! and every next level is the hash of two subtrees of the previous one. The tables
+//! are written to `OUT_DIR` and included by `tree/hasher.rs`.
+
+use std::fmt::Write as _;
+use std::path::Path;
+
+use blake2::digest::consts::U32;
+use blake2::{Blake2b, Digest};
+use dusk_bls12_381::BlsScalar;
+
+// the event signatures and the Poseidon packing aren't needed here
+#[allow(dead_code)]
+#[path = "protocol_constants.rs"]
+mod protocol_constants;
+
+use protocol_constants::{MAX_DEPTH, ZEROS_SEED};
+
+fn blake2x256(input: &[u8]) -> [u8; 32] {
+    Blake2b::<U32>::digest(input).into()
+}
+
+/// Same limb layout as `Poseidon::bytes_to_u64`
+fn bytes_to_u64(bytes: [u8; 32]) -> [u64; 4] {
+    let mut result = [0; 4];
+
+    for (limb, bytes_8) in result.iter_mut().zip(bytes.chunks_exact(8)) {
+        *limb = u64::from_be_bytes(bytes_8.try_into().unwrap());
+    }
+
+    result
+}
+
+/// Same limb layout as `Poseidon::u64_to_bytes`
+fn u64_to_bytes(array: [u64; 4]) -> [u8; 32] {
+    let mut result = [0; 32];
+
+    for (bytes_8, limb) in result.chunks_exact_mut(8).zip(array) {
+        bytes_8.copy_from_slice(&limb.to_be_bytes());
+    }
+
+    result
+}
+
+fn blake_zeros() -> Vec<[u8; 32]> {
+    let mut zeros = vec![blake2x256(ZEROS_SEED)];
+
+    while zeros.len() < MAX_DEPTH {
+        let last = zeros[zeros.len() - 1];
+        zeros.push(blake2x256(&[last, last].concat()));
+    }
+
+    zeros
+}
+
+fn poseidon_zeros() -> Vec<[u8; 32]> {
+    let mut scalar = BlsScalar::from_raw(bytes_to_u64(blake2x256(ZEROS_SEED)));
+    let mut zeros = Vec::with_capacity(MAX_DEPTH);
+
+    while zeros.len() < MAX_DEPTH {
+        zeros.push(u64_to_bytes(*scalar.internal_repr()));
+        scalar = dusk_poseidon::sponge::hash(&[scalar, scalar]);
+    }
+
+    zeros
+}
+
+/// Render the table as an array expression of `hex!` literals
+fn render(zeros: &[[u8; 32]]) -> String {
+    let mut table = String::from("[\n");
+
+    for zero in zeros {
+        let hex: String = zero.iter().map(|byte| format!("{:02X}", byte)).collect();
+        writeln!(table, "    hex!(\"{}\"),", hex).unwrap();
+    }
+
+    table.push(']');
+    table
+}
+
+fn main() {
+    let out_dir = std::env::var("OUT_DIR").unwrap();
+    let out_dir = Path::new(&out_dir);
+
+    std::fs::write(out_dir.join("blake_zeros.rs"), render(&blake_zeros())).unwrap();
+    std::fs::write(out_dir.join("poseidon_zeros.rs"), render(&poseidon_zeros())).unwrap();
+
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=protocol_constants.rs");
+}
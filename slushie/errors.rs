@@ -0,0 +1,44 @@
+//! Errors returned from the contract messages
+//!
+//! Kept out of the `#[ink::contract]` module, which re-exports `Result`, as they
+//! don't depend on the contract environment.
+
+use crate::tree::merkle_tree::MerkleTreeError;
+
+/// Errors which my be returned from the smart contract
+#[derive(Debug, PartialEq, Eq, scale::Encode, scale::Decode)]
+#[cfg_attr(feature = "std", derive(scale_info::TypeInfo))]
+pub enum Error {
+    DepositFailure,
+    MerkleTreeIsFull,
+    MerkleTreeInvalidDepth,
+    InvalidTransferredAmount,
+    InvalidDepositSize,
+    InsufficientFunds,
+    NullifierAlreadyUsed,
+    UnknownRoot,
+    DuplicateCommitment,
+    UnexpectedTransferredValue,
+    FeatureDisabled,
+    LayoutMismatch,
+    CallerIsNotOwner,
+    BatchTooLarge,
+    AccountingViolation,
+    WithdrawalsPaused,
+    PayoutNotFound,
+    CircuitBreakerTripped,
+    InvalidCircuitBreaker,
+    NoAccountingViolation,
+}
+
+impl From<MerkleTreeError> for Error {
+    fn from(err: MerkleTreeError) -> Self {
+        match err {
+            MerkleTreeError::MerkleTreeIsFull => Error::MerkleTreeIsFull,
+            MerkleTreeError::DepthTooLong => Error::MerkleTreeInvalidDepth,
+            MerkleTreeError::DepthIsZero => Error::MerkleTreeInvalidDepth,
+        }
+    }
+}
+
+pub type Result<T> = core::result::Result<T, Error>;
@@ -0,0 +1,156 @@
+//! Canonical text format of hashes for off-chain tools and tests
+//!
+//! Hashes are written as `0x` followed by lowercase hex digits. Parsing is
+//! strict: the prefix, the exact length and lowercase digits are required,
+//! so a value only has one accepted spelling.
+
+use std::fmt;
+
+/// Errors of parsing a hex string with `from_hex`
+#[derive(Debug, PartialEq, Eq)]
+pub enum FromHexError {
+    /// The string doesn't start with `0x`
+    MissingPrefix,
+    /// The number of hex digits isn't twice the expected number of bytes
+    InvalidLength { expected: usize, actual: usize },
+    /// The character isn't a lowercase hex digit
+    InvalidCharacter(char),
+}
+
+impl fmt::Display for FromHexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FromHexError::MissingPrefix => write!(f, "hex string must start with 0x"),
+            FromHexError::InvalidLength { expected, actual } => {
+                write!(f, "expected {} hex digits, got {}", expected, actual)
+            }
+            FromHexError::InvalidCharacter(c) => {
+                write!(
+                    f,
+                    "invalid character {:?}, expected a lowercase hex digit",
+                    c
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for FromHexError {}
+
+/// `0x` prefixed lowercase hex representation of the bytes
+pub fn to_hex(bytes: &[u8]) -> String {
+    let hex: String = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+    format!("0x{}", hex)
+}
+
+/// Parse `N` bytes written by `to_hex`
+pub fn from_hex<const N: usize>(text: &str) -> Result<[u8; N], FromHexError> {
+    let digits = text.strip_prefix("0x").ok_or(FromHexError::MissingPrefix)?;
+
+    if let Some(c) = digits.chars().find(|c| !matches!(c, '0'..='9' | 'a'..='f')) {
+        return Err(FromHexError::InvalidCharacter(c));
+    }
+
+    // only ASCII digits are left, so the length in bytes is the number of digits
+    if digits.len() != 2 * N {
+        return Err(FromHexError::InvalidLength {
+            expected: 2 * N,
+            actual: digits.len(),
+        });
+    }
+
+    let mut result = [0; N];
+    for (byte, pair) in result.iter_mut().zip(digits.as_bytes().chunks_exact(2)) {
+        *byte = (hex_digit(pair[0]) << 4) | hex_digit(pair[1]);
+    }
+
+    Ok(result)
+}
+
+/// Value of a lowercase hex digit, already validated by `from_hex`
+fn hex_digit(digit: u8) -> u8 {
+    match digit {
+        b'0'..=b'9' => digit - b'0',
+        _ => digit - b'a' + 10,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rand::{Rng, SeedableRng};
+
+    #[test]
+    fn test_hex_roundtrip() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+
+        for _ in 0..256 {
+            let hash: [u8; 32] = rng.gen();
+            let text = to_hex(&hash);
+
+            assert_eq!(text.len(), 66);
+            assert_eq!(text, text.to_lowercase());
+            assert_eq!(from_hex::<32>(&text), Ok(hash));
+        }
+    }
+
+    #[test]
+    fn test_to_hex() {
+        assert_eq!(to_hex(&[]), "0x");
+        assert_eq!(to_hex(&[0x00, 0xab, 0x0f]), "0x00ab0f");
+    }
+
+    #[test]
+    fn test_from_hex_rejects_missing_prefix() {
+        assert_eq!(from_hex::<2>("00ab"), Err(FromHexError::MissingPrefix));
+        assert_eq!(from_hex::<2>("0X00ab"), Err(FromHexError::MissingPrefix));
+        assert_eq!(from_hex::<2>(" 0x00ab"), Err(FromHexError::MissingPrefix));
+    }
+
+    #[test]
+    fn test_from_hex_rejects_uppercase() {
+        assert_eq!(
+            from_hex::<2>("0x00AB"),
+            Err(FromHexError::InvalidCharacter('A'))
+        );
+        assert_eq!(
+            from_hex::<2>("0x00aB"),
+            Err(FromHexError::InvalidCharacter('B'))
+        );
+    }
+
+    #[test]
+    fn test_from_hex_rejects_invalid_characters() {
+        assert_eq!(
+            from_hex::<2>("0x00ag"),
+            Err(FromHexError::InvalidCharacter('g'))
+        );
+        assert_eq!(
+            from_hex::<2>("0x00ab "),
+            Err(FromHexError::InvalidCharacter(' '))
+        );
+        assert_eq!(
+            from_hex::<2>("0x00é"),
+            Err(FromHexError::InvalidCharacter('é'))
+        );
+    }
+
+    #[test]
+    fn test_from_hex_rejects_wrong_length() {
+        assert_eq!(
+            from_hex::<2>("0x00a"),
+            Err(FromHexError::InvalidLength {
+                expected: 4,
+                actual: 3
+            })
+        );
+        assert_eq!(
+            from_hex::<32>("0x00ab"),
+            Err(FromHexError::InvalidLength {
+                expected: 64,
+                actual: 4
+            })
+        );
+    }
+}
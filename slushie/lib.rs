@@ -27,74 +27,397 @@
 //! Tokens can be withdrawn at any time, but for security reasons, it's better to wait some period say, 24 hours
 //! after deposit and before withdrawal to make it harder to track the token transfer.
 //! Tokens can be withdrawn only in a constant `deposit_size` amount by anyone who knows the nullifier and the root hash.
+//!
+//! ## Events
+//!
+//! Every event has the event signature (`Slushie::Deposited`, `Slushie::Withdrawn`) as its first topic,
+//! followed by one topic per indexed field:
+//!
+//! - `Deposited` – the `commitment`, so a depositor can find the leaf of their note.
+//! - `Withdrawn` – the `nullifier_hash`, so a note owner can check whether the note is spent.
+//!
+//! Every event starts with a `version` field equal to `EVENT_VERSION`, which is bumped whenever
+//! the layout of any event changes, so decoders can tell the layouts apart. Events emitted before
+//! versioning was introduced (version 0) have no `version` field and start with the commitment
+//! or the nullifier hash.
+//!
+//! Timestamps are deliberately not indexed. They are part of the event data, but a topic would let
+//! anyone cheaply filter deposits and withdrawals by time window, which is the main way to link them.
 
 #![cfg_attr(not(feature = "std"), no_std)]
+// no feature may pull in a crate its build doesn't use, see `scripts/check-no-std.sh`
+#![deny(unused_crate_dependencies)]
+
+#[cfg(not(any(
+    feature = "contract-hasher-poseidon",
+    feature = "contract-hasher-blake"
+)))]
+compile_error!(
+    "select the contract's merkle tree hasher with `contract-hasher-poseidon` or `contract-hasher-blake`"
+);
 
 use ink_lang as ink;
 
+mod errors;
+#[cfg(feature = "std")]
+pub mod format;
+mod math;
+mod messages;
+pub mod protocol_constants;
+mod storage;
+pub mod subscriber;
+#[cfg(test)]
+mod testing;
 mod tree;
 
+pub use tree::light;
+
+/// Environment types of the contract for the modules outside of it, the
+/// aliases `#[ink::contract]` generates are private to the contract module
+type Environment = <slushie::Slushie as ink_lang::reflect::ContractEnv>::Env;
+type AccountId = <Environment as ink_env::Environment>::AccountId;
+type Balance = <Environment as ink_env::Environment>::Balance;
+type BlockNumber = <Environment as ink_env::Environment>::BlockNumber;
+type Timestamp = <Environment as ink_env::Environment>::Timestamp;
+
 #[ink::contract]
 mod slushie {
     use super::*;
-    use crate::tree::hasher::Poseidon;
-    use crate::tree::merkle_tree::{
-        MerkleTree, MerkleTreeError, DEFAULT_ROOT_HISTORY_SIZE, MAX_DEPTH,
-    };
+    pub use crate::errors::Result;
+    use crate::tree::hasher::MerkleTreeHasher;
+    use crate::tree::merkle_tree::{MerkleProof, MerkleTree, DEFAULT_ROOT_HISTORY_SIZE, MAX_DEPTH};
+    use ink_prelude::string::String;
+    use ink_prelude::vec::Vec;
+
+    /// Hasher of the contract's merkle tree, `contract-hasher-blake` takes
+    /// precedence as `contract-hasher-poseidon` is enabled by default
+    #[cfg(feature = "contract-hasher-blake")]
+    pub(crate) type TreeHasher = crate::tree::hasher::Blake;
+    #[cfg(not(feature = "contract-hasher-blake"))]
+    pub(crate) type TreeHasher = crate::tree::hasher::Poseidon;
+
+    #[cfg(feature = "contract-hasher-blake")]
+    pub(crate) const TREE_HASHER_KIND: HasherKind = HasherKind::Blake;
+    #[cfg(not(feature = "contract-hasher-blake"))]
+    pub(crate) const TREE_HASHER_KIND: HasherKind = HasherKind::Poseidon;
+
+    pub(crate) type PoseidonHash = <TreeHasher as MerkleTreeHasher>::Output;
+
+    /// Caller defined memo attached to deposits made on behalf of someone
+    pub type Memo = [u8; 32];
+
+    /// Version of the events layout, see the crate docs
+    pub const EVENT_VERSION: u8 = 2;
 
-    type PoseidonHash = [u8; 32];
+    /// Version of the storage layout this code expects, bumped by every
+    /// upgrade which changes the layout and ships a migration in `migrate`
+    pub const STORAGE_LAYOUT_VERSION: u32 = 1;
+
+    /// Maximum number of nullifier hashes in one `are_nullifiers_used` query
+    pub const MAX_NULLIFIER_BATCH: usize = 128;
 
     #[ink(storage)]
     #[derive(ink_storage::traits::SpreadAllocate)]
     pub struct Slushie {
-        merkle_tree: MerkleTree<MAX_DEPTH, DEFAULT_ROOT_HISTORY_SIZE, Poseidon>,
-        deposit_size: Balance,
-        used_nullifiers: ink_storage::Mapping<PoseidonHash, bool>,
+        pub(crate) merkle_tree: MerkleTree<MAX_DEPTH, DEFAULT_ROOT_HISTORY_SIZE, TreeHasher>,
+        pub(crate) deposit_size: Balance,
+        /// Keyed by the full nullifier hash on purpose: `Mapping` stores every
+        /// entry under blake2x256 of its encoded key, which is 32 bytes for any
+        /// key size. A truncated key would save no storage and would let two
+        /// notes collide.
+        pub(crate) used_nullifiers: ink_storage::Mapping<PoseidonHash, bool>,
+        pub(crate) commitments: ink_storage::Mapping<PoseidonHash, u32>,
+        pub(crate) track_deposit_times: bool,
+        pub(crate) deposit_times: ink_storage::Mapping<u32, Timestamp>,
+        pub(crate) store_insertion_roots: bool,
+        pub(crate) insertion_roots: ink_storage::Mapping<u32, PoseidonHash>,
+        pub(crate) owner: AccountId,
+        pub(crate) storage_layout_version: u32,
+        pub(crate) withdrawn_count: u64,
+        pub(crate) withdrawals_paused: bool,
+        pub(crate) pull_payments: bool,
+        /// Payouts recorded by `withdraw` in the pull payments mode, keyed
+        /// by the spent nullifier hash
+        pub(crate) payouts: ink_storage::Mapping<PoseidonHash, Payout>,
+        /// Sum of the recorded payouts, reserved from the contract balance
+        pub(crate) pending_payouts: Balance,
+        /// Share of the principal in percent which may be withdrawn within
+        /// the outflow window, 0 disables the circuit breaker
+        pub(crate) max_outflow_percent: u8,
+        /// Length of an outflow window in blocks
+        pub(crate) outflow_window_blocks: BlockNumber,
+        /// Index of the window `outflow_current` belongs to, the block number
+        /// divided by the window length
+        pub(crate) outflow_window: BlockNumber,
+        /// Withdrawn within the window `outflow_window`
+        pub(crate) outflow_current: Balance,
+        /// Withdrawn within the window before `outflow_window`
+        pub(crate) outflow_previous: Balance,
+        /// Withdrawals are paused until the owner resets the breaker
+        pub(crate) circuit_breaker_tripped: bool,
+        /// Contract notified about every deposit and withdrawal, see `subscriber`
+        pub(crate) stats_subscriber: Option<AccountId>,
     }
 
     /// Deposit event when the tokens deposited successfully
     #[ink(event)]
     pub struct Deposited {
+        /// Events layout version
+        pub(crate) version: u8,
+
+        /// Deposited commitment, indexed to look up the note's leaf
         #[ink(topic)]
-        hash: PoseidonHash,
+        pub(crate) commitment: PoseidonHash,
 
-        timestamp: Timestamp,
+        /// Not indexed, see the crate docs on linkage by time
+        pub(crate) timestamp: Timestamp,
     }
 
     /// Withdraw event when the tokens withdrawn successfully
     #[ink(event)]
     pub struct Withdrawn {
+        /// Events layout version
+        pub(crate) version: u8,
+
+        /// Spent nullifier hash, indexed to check whether a note is spent
+        #[ink(topic)]
+        pub(crate) nullifier_hash: PoseidonHash,
+
+        /// Not indexed, see the crate docs on linkage by time
+        pub(crate) timestamp: Timestamp,
+    }
+
+    /// Deposit event when the tokens deposited on behalf of another account
+    ///
+    /// Emitted by `deposit_for` right after `Deposited` when the caller
+    /// differs from the declared beneficiary
+    #[ink(event)]
+    pub struct DepositedFor {
+        /// Events layout version
+        pub(crate) version: u8,
+
+        /// Deposited commitment, the same as in the preceding `Deposited`
         #[ink(topic)]
-        hash: PoseidonHash,
+        pub(crate) commitment: PoseidonHash,
+
+        /// Beneficiary declared by the caller, indexed so a service can
+        /// list the deposits it made for an account
+        #[ink(topic)]
+        pub(crate) beneficiary: AccountId,
+
+        /// Optional caller defined memo, e.g. a payroll reference
+        pub(crate) memo: Option<Memo>,
+    }
+
+    /// Pool instantiated, emitted by the constructors after the self-checks
+    #[ink(event)]
+    pub struct PoolInitialized {
+        /// Events layout version
+        pub(crate) version: u8,
+
+        /// Amount of every deposit and withdrawal
+        pub(crate) deposit_size: Balance,
+
+        /// Depth of the merkle_tree
+        pub(crate) depth: u32,
+
+        /// Number of roots withdrawals can be made against
+        pub(crate) root_history_size: u32,
+
+        /// Top of the ZEROS ladder, recomputed from the zero leaf
+        pub(crate) zeros_top: PoseidonHash,
+
+        /// The same as returned from `get_build_info`
+        pub(crate) build_info: BuildInfo,
+    }
+
+    /// Withdrawals paused after an accounting invariant violation, emitted
+    /// by `report_accounting_violation`
+    #[ink(event)]
+    pub struct EmergencyPaused {
+        /// Events layout version
+        pub(crate) version: u8,
+
+        /// Successful withdrawals so far
+        pub(crate) withdrawn_count: u64,
+
+        /// Deposits so far
+        pub(crate) deposit_count: u64,
+    }
+
+    /// Withdrawal paid out by `claim_payout` in the pull payments mode
+    #[ink(event)]
+    pub struct PayoutClaimed {
+        /// Events layout version
+        pub(crate) version: u8,
+
+        /// Nullifier hash spent by the withdrawal, the same as in `Withdrawn`
+        #[ink(topic)]
+        pub(crate) nullifier_hash: PoseidonHash,
+
+        /// Account which received the tokens
+        pub(crate) recipient: AccountId,
+
+        /// Amount of tokens paid to the recipient
+        pub(crate) amount: Balance,
+    }
+
+    /// Withdrawals paused by the circuit breaker, see `withdraw`
+    #[ink(event)]
+    pub struct CircuitBreakerTripped {
+        /// Events layout version
+        pub(crate) version: u8,
+
+        /// Withdrawn within the sliding outflow window, including the
+        /// withdrawal which tripped the breaker, see `CircuitBreakerStatus`
+        pub(crate) outflow: Balance,
 
-        timestamp: Timestamp,
+        /// Principal the outflow is compared with: the deposits left in
+        /// the pool plus the outflow
+        pub(crate) principal: Balance,
     }
 
-    /// Errors which my be returned from the smart contract
+    /// Optional features of a pool, set at instantiation by `new_with_options`
+    #[derive(Debug, Clone, Default, PartialEq, Eq, scale::Encode, scale::Decode)]
+    #[cfg_attr(feature = "std", derive(scale_info::TypeInfo))]
+    pub struct PoolOptions {
+        /// Store the root right after every insertion, returned with the
+        /// commitment info, at the cost of an extra storage entry per deposit
+        pub store_insertion_roots: bool,
+        /// Store the block timestamp of every deposit by leaf index, again
+        /// at the cost of an extra storage entry per deposit
+        pub track_deposit_times: bool,
+        /// `withdraw` only records the payout and `claim_payout` transfers
+        /// it in a separate call, for chains where the payout inside
+        /// `withdraw` doesn't fit
+        pub pull_payments: bool,
+        /// Share of the principal in percent which may be withdrawn within
+        /// the outflow window before the circuit breaker trips, see
+        /// `withdraw`, 0 disables the breaker
+        pub max_outflow_percent: u8,
+        /// Length of the sliding outflow window in blocks
+        pub outflow_window_blocks: BlockNumber,
+    }
+
+    /// Payout recorded by `withdraw` in the pull payments mode
+    #[derive(
+        Debug,
+        Clone,
+        PartialEq,
+        Eq,
+        scale::Encode,
+        scale::Decode,
+        ink_storage::traits::PackedLayout,
+        ink_storage::traits::SpreadLayout,
+    )]
+    #[cfg_attr(
+        feature = "std",
+        derive(scale_info::TypeInfo, ink_storage::traits::StorageLayout)
+    )]
+    pub struct Payout {
+        /// Account the tokens are paid to, whoever claims them
+        pub recipient: AccountId,
+        /// Amount of tokens to pay
+        pub amount: Balance,
+    }
+
+    /// Withdrawal data returned from the `withdraw` on success
     #[derive(Debug, PartialEq, Eq, scale::Encode, scale::Decode)]
     #[cfg_attr(feature = "std", derive(scale_info::TypeInfo))]
-    pub enum Error {
-        DepositFailure,
-        MerkleTreeIsFull,
-        MerkleTreeInvalidDepth,
-        InvalidTransferredAmount,
-        InvalidDepositSize,
-        InsufficientFunds,
-        NullifierAlreadyUsed,
-        UnknownRoot,
+    pub struct WithdrawalReceipt {
+        /// Nullifier hash which was spent by the withdrawal
+        pub nullifier_hash: PoseidonHash,
+        /// Account which received the tokens
+        pub recipient: AccountId,
+        /// Amount of tokens paid to the recipient
+        pub amount: Balance,
+        /// Root the withdrawal was matched against
+        pub root: PoseidonHash,
+        /// Block timestamp of the withdrawal
+        pub timestamp: Timestamp,
     }
 
-    impl From<MerkleTreeError> for Error {
-        fn from(err: MerkleTreeError) -> Self {
-            match err {
-                MerkleTreeError::MerkleTreeIsFull => Error::MerkleTreeIsFull,
-                MerkleTreeError::DepthTooLong => Error::MerkleTreeInvalidDepth,
-                MerkleTreeError::DepthIsZero => Error::MerkleTreeInvalidDepth,
-            }
-        }
+    /// Inclusion data of the deposited commitment
+    #[derive(Debug, PartialEq, Eq, scale::Encode, scale::Decode)]
+    #[cfg_attr(feature = "std", derive(scale_info::TypeInfo))]
+    pub struct CommitmentInfo {
+        /// Index of the leaf in the merkle_tree
+        pub leaf_index: u32,
+        /// Block timestamp of the deposit, if the contract tracks them
+        pub deposit_timestamp: Option<Timestamp>,
+        /// Root right after the insertion, if the contract stores them
+        pub root_at_insertion: Option<PoseidonHash>,
     }
 
-    pub type Result<T> = core::result::Result<T, Error>;
+    /// Pool counters returned from `get_stats`
+    #[derive(Debug, PartialEq, Eq, scale::Encode, scale::Decode)]
+    #[cfg_attr(feature = "std", derive(scale_info::TypeInfo))]
+    pub struct PoolStats {
+        /// Number of deposits, i.e. leaves in the merkle_tree
+        pub deposit_count: u64,
+        /// Number of successful withdrawals
+        pub withdrawn_count: u64,
+        /// Whether withdrawals are paused after an accounting violation
+        pub withdrawals_paused: bool,
+    }
+
+    /// Circuit breaker state returned from `get_circuit_breaker`
+    #[derive(Debug, PartialEq, Eq, scale::Encode, scale::Decode)]
+    #[cfg_attr(feature = "std", derive(scale_info::TypeInfo))]
+    pub struct CircuitBreakerStatus {
+        /// Share of the principal in percent which may be withdrawn within
+        /// the outflow window, 0 if the breaker is disabled
+        pub max_outflow_percent: u8,
+        /// Length of the sliding outflow window in blocks
+        pub window_blocks: BlockNumber,
+        /// Whether withdrawals are paused until the owner resets the breaker
+        pub tripped: bool,
+        /// Withdrawn within the last `window_blocks` blocks: the outflow of
+        /// the current fixed window plus the outflow of the previous one,
+        /// weighted by the share of it the sliding window still covers
+        pub outflow: Balance,
+    }
+
+    /// Hasher of the contract's merkle tree
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, scale::Encode, scale::Decode)]
+    #[cfg_attr(feature = "std", derive(scale_info::TypeInfo))]
+    pub enum HasherKind {
+        /// Poseidon over BLS12-381, the default
+        Poseidon,
+        /// Blake2x256, with the `contract-hasher-blake` feature
+        Blake,
+    }
+
+    /// Build metadata compiled into the contract, returned from `get_build_info`
+    #[derive(Debug, Clone, PartialEq, Eq, scale::Encode, scale::Decode)]
+    #[cfg_attr(feature = "std", derive(scale_info::TypeInfo))]
+    pub struct BuildInfo {
+        /// Version of the slushie crate
+        pub crate_version: String,
+        /// Git commit passed in `SLUSHIE_GIT_COMMIT` at build time, None if
+        /// it wasn't set or was empty
+        pub git_commit: Option<String>,
+        /// Enabled cargo features
+        pub features: Vec<String>,
+        /// Hasher of the merkle_tree
+        pub hasher: HasherKind,
+        /// Depth of the merkle_tree
+        pub max_depth: u32,
+        /// Version of the protocol constants
+        pub protocol_version: u32,
+    }
+
+    /// Position of a root in the roots history
+    #[derive(Debug, PartialEq, Eq, scale::Encode, scale::Decode)]
+    #[cfg_attr(feature = "std", derive(scale_info::TypeInfo))]
+    pub enum RootStatus {
+        /// The last root of the merkle_tree
+        Current,
+        /// Still in the history, was the last root `age` insertions ago
+        Historical { age: u32 },
+        /// Not in the history, withdrawals against it fail
+        Unknown,
+    }
 
     impl Slushie {
         /// create a new Slushie contract
@@ -106,69 +429,253 @@ mod slushie {
         /// instantiated.
         #[ink(constructor)]
         pub fn new(deposit_size: Balance) -> Self {
-            ink::utils::initialize_contract(|me: &mut Self| {
-                *me = Self {
-                    merkle_tree: MerkleTree::<MAX_DEPTH, DEFAULT_ROOT_HISTORY_SIZE, Poseidon>::new(
-                    )
-                    .unwrap(),
-                    deposit_size,
-                    used_nullifiers: Default::default(),
-                };
-            })
+            Self::new_with_options(deposit_size, PoolOptions::default())
+        }
+
+        /// create a new Slushie contract with optional features
+        ///
+        /// Takes the deposit_size Balance amount like `new`,
+        /// the features are described by `PoolOptions`.
+        /// The circuit breaker compares the outflow within
+        /// the last outflow_window_blocks blocks with the
+        /// principal, see `CircuitBreakerStatus`.
+        #[ink(constructor)]
+        pub fn new_with_options(deposit_size: Balance, options: PoolOptions) -> Self {
+            // a wrongly compiled hasher would only show up as failing withdrawals
+            let zeros_top = Self::zeros_ladder_top(&TreeHasher::ZEROS);
+            assert!(
+                Self::is_valid_circuit_breaker(
+                    options.max_outflow_percent,
+                    options.outflow_window_blocks
+                ),
+                "invalid circuit breaker parameters"
+            );
+
+            // mappings are left as allocated by `SpreadAllocate`, so each
+            // of them gets its own storage key
+            let contract = ink::utils::initialize_contract(|me: &mut Self| {
+                me.merkle_tree =
+                    MerkleTree::<MAX_DEPTH, DEFAULT_ROOT_HISTORY_SIZE, TreeHasher>::new_checked();
+                me.deposit_size = deposit_size;
+                me.store_insertion_roots = options.store_insertion_roots;
+                me.track_deposit_times = options.track_deposit_times;
+                me.pull_payments = options.pull_payments;
+                me.max_outflow_percent = options.max_outflow_percent;
+                me.outflow_window_blocks = options.outflow_window_blocks;
+                me.outflow_window = me.outflow_window_index();
+                me.owner = Self::env().caller();
+                me.storage_layout_version = STORAGE_LAYOUT_VERSION;
+            });
+
+            Self::env().emit_event(PoolInitialized {
+                version: EVENT_VERSION,
+                deposit_size,
+                depth: MAX_DEPTH as u32,
+                root_history_size: DEFAULT_ROOT_HISTORY_SIZE as u32,
+                zeros_top,
+                build_info: Self::build_info(),
+            });
+
+            contract
         }
 
         /// Deposit a fixed amount of tokens into mixer
         ///
         /// Returns the merkle_tree root hash after insertion
+        ///
+        /// Front-running: anyone watching the transaction pool can deposit the
+        /// same commitment first with their own funds. The original deposit then
+        /// fails with `DuplicateCommitment` and the depositor keeps their tokens
+        /// (minus the fee). The attacker gains nothing: only the one who knows
+        /// the commitment's preimage, i.e. the original depositor, can withdraw
+        /// the note the attacker has just funded. Clients should dry-run
+        /// `can_deposit` right before signing to fail before paying any fee.
         #[ink(message, payable)]
         pub fn deposit(&mut self, commitment: PoseidonHash) -> Result<PoseidonHash> {
-            if self.env().transferred_value() != self.deposit_size {
-                return Err(Error::InvalidTransferredAmount);
-            }
+            self.insert_deposit(commitment)
+        }
 
-            self.merkle_tree.insert(commitment)?;
+        /// Deposit a fixed amount of tokens into mixer on behalf of the beneficiary
+        ///
+        /// For services funding deposits of their users: the beneficiary
+        /// creates the note and hands only the commitment to the caller, who
+        /// pays. Works exactly like `deposit` and additionally emits
+        /// `DepositedFor` with the beneficiary and the memo if the caller is
+        /// not the beneficiary itself.
+        ///
+        /// Returns the merkle_tree root hash after insertion
+        #[ink(message, payable)]
+        pub fn deposit_for(
+            &mut self,
+            commitment: PoseidonHash,
+            beneficiary: AccountId,
+            memo: Option<Memo>,
+        ) -> Result<PoseidonHash> {
+            self.insert_deposit_for(commitment, beneficiary, memo)
+        }
 
-            self.env().emit_event(Deposited {
-                hash: commitment,
-                timestamp: self.env().block_timestamp(),
-            });
+        /// Check that the commitment can be deposited right now
+        ///
+        /// Runs the same checks as `deposit`, except the transferred value
+        #[ink(message)]
+        pub fn can_deposit(&self, commitment: PoseidonHash) -> Result<()> {
+            self.ensure_layout()?;
 
-            Ok(self.merkle_tree.get_last_root() as PoseidonHash)
+            self.validate_deposit(commitment)
         }
 
         /// Withdraw a fixed amount of tokens from the mixer
         ///
         /// Can be withdrawn by anyone who knows the nullifier and the correct root hash
+        ///
+        /// Must be called without transferred value, otherwise the value
+        /// would be left in the contract balance without being credited.
+        /// The message is payable only so that attached value reaches the
+        /// message and is refused with `UnexpectedTransferredValue`, instead
+        /// of trapping in the dispatch with an opaque error.
+        ///
+        /// Returns the receipt with the withdrawal data
+        ///
+        /// The checks are ordered from the cheapest to the most expensive on
+        /// purpose: a duplicate submission of an already spent nullifier (e.g.
+        /// by a second relayer or a retry) is rejected with a single storage
+        /// read, before the roots history scan and any proof verification.
+        ///
+        /// The withdrawal is all or nothing: the payout is the only transfer
+        /// and happens before the nullifier is spent. If it fails the message
+        /// returns an error, which reverts every storage change of the call,
+        /// so no partial payout persists and the nullifier stays unspent.
+        ///
+        /// In the pull payments mode nothing is transferred: the payout is
+        /// recorded for `claim_payout` and reserved from the contract balance.
+        ///
+        /// With the circuit breaker enabled, a withdrawal after which more
+        /// than `max_outflow_percent` of the principal has left within the
+        /// current and the previous outflow window still succeeds, but trips
+        /// the breaker and emits `CircuitBreakerTripped`. Later withdrawals
+        /// fail with `CircuitBreakerTripped` until the owner calls
+        /// `reset_circuit_breaker`. The principal is the deposits left in the
+        /// pool plus the outflow. Deposits are never blocked by the breaker.
+        #[ink(message, payable)]
+        pub fn withdraw(
+            &mut self,
+            nullifier_hash: PoseidonHash,
+            root: PoseidonHash,
+        ) -> Result<WithdrawalReceipt> {
+            self.execute_withdrawal(nullifier_hash, root)
+        }
+
+        /// Pay out a withdrawal recorded in the pull payments mode
+        ///
+        /// Can be called by anyone, the tokens always go to the recorded
+        /// recipient. The payout is removed once paid, so it can be claimed
+        /// only once. Returns `FeatureDisabled` in the push payments mode.
+        ///
+        /// Payable like `withdraw`, attached value is refused with
+        /// `UnexpectedTransferredValue`.
+        #[ink(message, payable)]
+        pub fn claim_payout(&mut self, nullifier_hash: PoseidonHash) -> Result<Payout> {
+            self.pay_out(nullifier_hash)
+        }
+
+        /// Returns the unclaimed payout of the withdrawal which spent the
+        /// nullifier hash, None if it's claimed or was paid out directly
         #[ink(message)]
-        pub fn withdraw(&mut self, nullifier_hash: PoseidonHash, root: PoseidonHash) -> Result<()> {
-            if !self.merkle_tree.is_known_root(root) {
-                return Err(Error::UnknownRoot);
-            }
+        pub fn get_payout(&self, nullifier_hash: PoseidonHash) -> Option<Payout> {
+            self.payouts.get(nullifier_hash)
+        }
 
-            if self.env().balance() < self.deposit_size {
-                return Err(Error::InsufficientFunds);
-            }
+        /// Check that the withdrawal would succeed right now
+        ///
+        /// Takes the same arguments as `withdraw` and runs all of its checks
+        /// without transferring the tokens or spending the nullifier, so
+        /// relayers can reject bad jobs with a dry-run before signing
+        ///
+        /// Payable like `withdraw`, so a dry-run with attached value gets the
+        /// same `UnexpectedTransferredValue`
+        #[ink(message, payable)]
+        pub fn check_withdraw(
+            &self,
+            nullifier_hash: PoseidonHash,
+            root: PoseidonHash,
+        ) -> Result<()> {
+            self.validate_withdraw(nullifier_hash, root)
+        }
 
-            if self.used_nullifiers.get(nullifier_hash).is_some() {
-                return Err(Error::NullifierAlreadyUsed);
-            }
+        /// Set up the circuit breaker, see `withdraw`
+        ///
+        /// Only the owner can call it. A percent of 0 disables the breaker,
+        /// otherwise it must be at most 100 with a non-zero window, or
+        /// `InvalidCircuitBreaker` is returned. The outflow so far is
+        /// forgotten, it was counted with the previous window length.
+        #[ink(message)]
+        pub fn set_circuit_breaker(
+            &mut self,
+            max_outflow_percent: u8,
+            window_blocks: BlockNumber,
+        ) -> Result<()> {
+            self.configure_circuit_breaker(max_outflow_percent, window_blocks)
+        }
 
-            if self
-                .env()
-                .transfer(self.env().caller(), self.deposit_size)
-                .is_err()
-            {
-                return Err(Error::InvalidDepositSize);
+        /// Resume withdrawals after the circuit breaker tripped
+        ///
+        /// Only the owner can call it, after investigating the outflow. The
+        /// outflow so far is forgotten, so the next withdrawal doesn't trip
+        /// the breaker right away again.
+        #[ink(message)]
+        pub fn reset_circuit_breaker(&mut self) -> Result<()> {
+            self.clear_circuit_breaker()
+        }
+
+        /// Returns the circuit breaker parameters, whether it tripped and
+        /// the outflow as of the current block
+        #[ink(message)]
+        pub fn get_circuit_breaker(&self) -> CircuitBreakerStatus {
+            self.circuit_breaker_status()
+        }
+
+        /// Returns whether each of the nullifier hashes is already used, in order
+        ///
+        /// Lets wallets check many candidate notes in one dry-run. Returns
+        /// `BatchTooLarge` for more than `MAX_NULLIFIER_BATCH` hashes.
+        #[ink(message)]
+        pub fn are_nullifiers_used(
+            &self,
+            nullifier_hashes: Vec<PoseidonHash>,
+        ) -> Result<Vec<bool>> {
+            self.nullifiers_used(nullifier_hashes)
+        }
+
+        /// Returns the deposit and withdrawal counters of the pool
+        #[ink(message)]
+        pub fn get_stats(&self) -> PoolStats {
+            PoolStats {
+                deposit_count: self.merkle_tree.next_index,
+                withdrawn_count: self.withdrawn_count,
+                withdrawals_paused: self.withdrawals_paused,
             }
+        }
 
-            self.used_nullifiers.insert(nullifier_hash, &true);
+        /// Set or clear the contract notified about every deposit and
+        /// withdrawal, see `subscriber`
+        ///
+        /// Only the owner can call it. The subscriber is called with the
+        /// aggregate data of the operation, its failures are ignored.
+        #[ink(message)]
+        pub fn set_stats_subscriber(&mut self, subscriber: Option<AccountId>) -> Result<()> {
+            self.configure_stats_subscriber(subscriber)
+        }
 
-            self.env().emit_event(Withdrawn {
-                hash: nullifier_hash,
-                timestamp: self.env().block_timestamp(),
-            });
+        /// Returns the stats subscriber, if there is one
+        #[ink(message)]
+        pub fn get_stats_subscriber(&self) -> Option<AccountId> {
+            self.stats_subscriber
+        }
 
-            Ok(())
+        /// Returns the build metadata, telling which code the pool runs
+        #[ink(message)]
+        pub fn get_build_info(&self) -> BuildInfo {
+            Self::build_info()
         }
 
         /// Returns the merkle_tree root hash
@@ -176,13 +683,90 @@ mod slushie {
         pub fn get_root_hash(&self) -> PoseidonHash {
             self.merkle_tree.get_last_root() as PoseidonHash
         }
+
+        /// Returns whether the root is the current one, a historical one or unknown
+        ///
+        /// A historical root is evicted after `DEFAULT_ROOT_HISTORY_SIZE - age`
+        /// more deposits, so relayers can prefer roots with a longer validity ahead
+        #[ink(message)]
+        pub fn get_root_status(&self, root: PoseidonHash) -> RootStatus {
+            self.root_status(root)
+        }
+
+        /// Check that the leaf is in the merkle_tree
+        ///
+        /// Recomputes the root from the leaf and the Merkle path and checks it's a known root.
+        /// Intended for integrations which only need to know that some deposit was made.
+        ///
+        /// WARNING: calling this with the commitment of one's own note publicly links
+        /// the caller to the deposit and destroys the privacy of the note.
+        #[ink(message)]
+        pub fn verify_inclusion(
+            &self,
+            leaf: PoseidonHash,
+            proof: MerkleProof<PoseidonHash>,
+        ) -> bool {
+            self.merkle_tree.verify_proof(leaf, &proof)
+        }
+
+        /// Returns the block timestamp of the deposit by the leaf index
+        ///
+        /// Returns `FeatureDisabled` error if the contract was
+        /// instantiated without track_deposit_times
+        #[ink(message)]
+        pub fn get_deposit_time(&self, leaf_index: u32) -> Result<Option<Timestamp>> {
+            self.deposit_time(leaf_index)
+        }
+
+        /// Migrate the storage to `STORAGE_LAYOUT_VERSION` after a code upgrade
+        ///
+        /// Only the owner, the account which instantiated the contract, can
+        /// call it. An upgrade which changes the storage layout bumps
+        /// `STORAGE_LAYOUT_VERSION` and adds a branch here migrating from the
+        /// previous version. Until the migration runs, every fallible message
+        /// returns `LayoutMismatch`. Migrating an up to date storage is a no-op.
+        #[ink(message)]
+        pub fn migrate(&mut self) -> Result<()> {
+            self.migrate_storage()
+        }
+
+        /// Pause withdrawals if the stored accounting is violated and emit
+        /// `EmergencyPaused`
+        ///
+        /// Anyone can call it. The accounting is violated if there are more
+        /// withdrawals than deposits or the balance doesn't cover the recorded
+        /// payouts. A violating `withdraw` only fails with `AccountingViolation`:
+        /// a failed message is reverted on-chain, so it can't pause anything.
+        /// This message succeeds instead, so the pause persists. Returns
+        /// `NoAccountingViolation` if the accounting is consistent and
+        /// `WithdrawalsPaused` if withdrawals are paused already.
+        #[ink(message)]
+        pub fn report_accounting_violation(&mut self) -> Result<()> {
+            self.pause_on_accounting_violation()
+        }
+
+        /// Returns the merkle_tree leaf index of the deposited commitment
+        #[ink(message)]
+        pub fn get_commitment_index(&self, commitment: PoseidonHash) -> Option<u32> {
+            self.commitments.get(commitment)
+        }
+
+        /// Returns the inclusion data of the deposited commitment
+        ///
+        /// `deposit_timestamp` and `root_at_insertion` are set only
+        /// if the contract was instantiated with track_deposit_times
+        /// and store_insertion_roots respectively
+        #[ink(message)]
+        pub fn get_commitment_info(&self, commitment: PoseidonHash) -> Option<CommitmentInfo> {
+            self.commitment_info(commitment)
+        }
     }
 
-    /// Unit tests
+    /// Unit tests of the constructors and the tree queries, the messages
+    /// are tested next to their helpers in `messages`
     #[cfg(test)]
     mod tests {
-        use super::*;
-        use hex_literal::hex;
+        use crate::testing::*;
 
         /// Imports `ink_lang` so we can use `#[ink::test]`.
         use ink_lang as ink;
@@ -194,140 +778,106 @@ mod slushie {
             assert_eq!(slushie.deposit_size, 13 as Balance);
             assert_eq!(
                 slushie.merkle_tree,
-                MerkleTree::<MAX_DEPTH, DEFAULT_ROOT_HISTORY_SIZE, Poseidon>::new().unwrap()
+                MerkleTree::<MAX_DEPTH, DEFAULT_ROOT_HISTORY_SIZE, TreeHasher>::new().unwrap()
             );
         }
 
-        /// can deposit funds with a proper `deposit_size`
+        /// - can't prove inclusion of a zero leaf in an empty pool
         #[ink::test]
-        fn deposit_works() {
-            let accounts = ink_env::test::default_accounts::<ink_env::DefaultEnvironment>();
-            let mut slushie: Slushie = Slushie::new(13);
-            let commitment: PoseidonHash =
-                hex!("0001020304050607 08090a0b0c0d0e0f 0001020304050607 08090a0b0c0d0e0f");
-
-            let initial_root_hash = slushie.get_root_hash();
+        fn verify_inclusion_in_empty_pool_fails() {
+            let slushie: Slushie = Slushie::new(13);
 
-            ink_env::test::set_caller::<Environment>(accounts.bob);
-            ink_env::test::set_value_transferred::<ink_env::DefaultEnvironment>(13);
-            let res = slushie.deposit(commitment);
-            assert!(res.is_ok());
+            let proof = MerkleProof {
+                leaf_index: 0,
+                siblings: TreeHasher::ZEROS.to_vec(),
+            };
 
-            let resulting_root_hash = slushie.get_root_hash();
-            assert_ne!(initial_root_hash, resulting_root_hash);
+            assert!(!slushie.verify_inclusion(TreeHasher::ZEROS[0], proof));
         }
 
-        /// can't deposit funds with an invalid `deposit_size`
+        /// - the constructor refuses invalid circuit breaker parameters
         #[ink::test]
-        fn deposit_invalid_amount_fails() {
-            let accounts = ink_env::test::default_accounts::<ink_env::DefaultEnvironment>();
-            let deposit_size = 13;
-            let invalid_deposit_size = 55;
-            let mut slushie: Slushie = Slushie::new(deposit_size);
-            let commitment: PoseidonHash =
-                hex!("0001020304050607 08090a0b0c0d0e0f 0001020304050607 08090a0b0c0d0e0f");
-
-            let initial_root_hash = slushie.get_root_hash();
-
-            ink_env::test::set_caller::<Environment>(accounts.bob);
-            ink_env::test::set_value_transferred::<ink_env::DefaultEnvironment>(
-                invalid_deposit_size,
+        #[should_panic(expected = "invalid circuit breaker parameters")]
+        fn constructor_rejects_invalid_circuit_breaker() {
+            let _: Slushie = Slushie::new_with_options(
+                13,
+                PoolOptions {
+                    max_outflow_percent: 30,
+                    ..Default::default()
+                },
             );
-            let res = slushie.deposit(commitment);
-            assert_eq!(res.unwrap_err(), Error::InvalidTransferredAmount);
-
-            let resulting_root_hash = slushie.get_root_hash();
-            assert_eq!(initial_root_hash, resulting_root_hash);
         }
 
-        /// can't deposit funds if account doesn't have enough money
-        ///
-        /// this case shouldn't be tested cause is a pallete, which
-        /// checks the sufficient amount of funds
-
-        /// - can withdraw funds with a proper deposit_size and hash
+        /// - can verify inclusion of the deposited leaves
         #[ink::test]
-        fn withdraw_works() {
-            let accounts = ink_env::test::default_accounts::<ink_env::DefaultEnvironment>();
-            let deposit_size: Balance = 13;
+        fn verify_inclusion_works() {
+            let deposit_size = 13;
             let mut slushie: Slushie = Slushie::new(deposit_size);
-            let hash: PoseidonHash =
-                hex!("0001020304050607 08090a0b0c0d0e0f 0001020304050607 08090a0b0c0d0e0f");
-
-            ink_env::test::set_caller::<Environment>(accounts.alice);
-            ink_env::test::set_value_transferred::<ink_env::DefaultEnvironment>(deposit_size);
-            let res = slushie.deposit(hash);
-            assert!(res.is_ok());
-
-            let resulting_root_hash = slushie.get_root_hash();
+            let first: PoseidonHash = [1; 32];
+            let second: PoseidonHash = [2; 32];
 
             ink_env::test::set_value_transferred::<ink_env::DefaultEnvironment>(deposit_size);
-            let res = slushie.withdraw(hash, resulting_root_hash);
-            assert!(res.is_ok());
-        }
+            slushie.deposit(first).unwrap();
+            slushie.deposit(second).unwrap();
 
-        /// - can withdraw funds with a proper deposit_size and hash by different account
-        #[ink::test]
-        fn withdraw_from_different_account_works() {
-            let accounts = ink_env::test::default_accounts::<ink_env::DefaultEnvironment>();
-            let deposit_size = 13;
-            let mut slushie: Slushie = Slushie::new(deposit_size);
-            let hash: PoseidonHash =
-                hex!("0001020304050607 08090a0b0c0d0e0f 0001020304050607 08090a0b0c0d0e0f");
+            let mut siblings = TreeHasher::ZEROS.to_vec();
+            siblings[0] = first;
+            let proof = MerkleProof {
+                leaf_index: 1,
+                siblings,
+            };
 
-            ink_env::test::set_caller::<Environment>(accounts.alice);
-            ink_env::test::set_value_transferred::<ink_env::DefaultEnvironment>(deposit_size);
-            let res = slushie.deposit(hash);
-            assert!(res.is_ok());
+            assert!(slushie.verify_inclusion(second, proof.clone()));
 
-            let resulting_root_hash = slushie.get_root_hash();
+            let mut tampered = proof.clone();
+            tampered.siblings[5] = [3; 32];
+            assert!(!slushie.verify_inclusion(second, tampered));
 
-            ink_env::test::set_caller::<Environment>(accounts.eve);
-            let res = slushie.withdraw(hash, resulting_root_hash);
-            assert!(res.is_ok());
+            assert!(!slushie.verify_inclusion(first, proof));
         }
 
-        /// - can't withdraw funds with invalid root hash
+        /// - can't verify inclusion against a root which is rotated out of the history
         #[ink::test]
-        fn withdraw_with_invalid_root_fails() {
-            let accounts = ink_env::test::default_accounts::<ink_env::DefaultEnvironment>();
+        fn verify_inclusion_with_stale_root_fails() {
             let deposit_size = 13;
             let mut slushie: Slushie = Slushie::new(deposit_size);
-            let hash: PoseidonHash =
-                hex!("0001020304050607 08090a0b0c0d0e0f 0001020304050607 08090a0b0c0d0e0f");
+            let leaf: PoseidonHash = [0; 32];
 
-            ink_env::test::set_caller::<Environment>(accounts.alice);
             ink_env::test::set_value_transferred::<ink_env::DefaultEnvironment>(deposit_size);
-            let res = slushie.deposit(hash);
-            assert!(res.is_ok());
+            slushie.deposit(leaf).unwrap();
+
+            let proof = MerkleProof {
+                leaf_index: 0,
+                siblings: TreeHasher::ZEROS.to_vec(),
+            };
+            assert!(slushie.verify_inclusion(leaf, proof.clone()));
 
-            let invalid_root_hash: PoseidonHash =
-                hex!("0000000000000000 0000000000000000 0001020304050607 08090a0b0c0d0e0f");
+            for i in 1..=DEFAULT_ROOT_HISTORY_SIZE {
+                slushie.deposit([i as u8; 32]).unwrap();
+            }
 
-            let res = slushie.withdraw(hash, invalid_root_hash);
-            assert_eq!(res.unwrap_err(), Error::UnknownRoot);
+            assert!(!slushie.verify_inclusion(leaf, proof));
         }
 
-        /// - can't double withdraw funds with a proper deposit_size and a valid hash
+        /// - the constructor emits `PoolInitialized` with the pool parameters
         #[ink::test]
-        fn withdraw_with_used_nullifier_fails() {
-            let accounts = ink_env::test::default_accounts::<ink_env::DefaultEnvironment>();
-            let deposit_size = 13;
-            let mut slushie: Slushie = Slushie::new(deposit_size);
-            let hash: PoseidonHash =
-                hex!("0001020304050607 08090a0b0c0d0e0f 0001020304050607 08090a0b0c0d0e0f");
-
-            ink_env::test::set_caller::<Environment>(accounts.alice);
-            ink_env::test::set_value_transferred::<ink_env::DefaultEnvironment>(deposit_size);
-            let res = slushie.deposit(hash);
-            assert!(res.is_ok());
-            let resulting_root_hash = slushie.get_root_hash();
+        fn constructor_emits_pool_initialized() {
+            let _slushie: Slushie = Slushie::new(13);
 
-            let res = slushie.withdraw(hash, resulting_root_hash);
-            assert!(res.is_ok());
+            let events = ink_env::test::recorded_events().collect::<Vec<_>>();
+            assert_eq!(events.len(), 1);
+            assert_eq!(
+                events[0].topics,
+                vec![signature_topic(POOL_INITIALIZED_EVENT)]
+            );
 
-            let res = slushie.withdraw(hash, resulting_root_hash);
-            assert_eq!(res.unwrap_err(), Error::NullifierAlreadyUsed);
+            let decoded = <PoolInitialized as Decode>::decode(&mut &events[0].data[1..]).unwrap();
+            assert_eq!(decoded.version, EVENT_VERSION);
+            assert_eq!(decoded.deposit_size, 13);
+            assert_eq!(decoded.depth, MAX_DEPTH as u32);
+            assert_eq!(decoded.root_history_size, DEFAULT_ROOT_HISTORY_SIZE as u32);
+            assert_eq!(decoded.zeros_top, TreeHasher::ZEROS[MAX_DEPTH - 1]);
+            assert_eq!(decoded.build_info, Slushie::build_info());
         }
     }
 }
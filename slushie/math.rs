@@ -0,0 +1,65 @@
+/// Checked arithmetic which returns the provided error instead of overflowing
+pub(crate) trait CheckedMath: Sized {
+    /// Add `rhs`, return `err` on overflow
+    fn checked_add_or<E>(self, rhs: Self, err: E) -> Result<Self, E>;
+
+    /// Subtract `rhs`, return `err` on underflow
+    fn checked_sub_or<E>(self, rhs: Self, err: E) -> Result<Self, E>;
+
+    /// Multiply by `rhs`, return `err` on overflow
+    fn checked_mul_or<E>(self, rhs: Self, err: E) -> Result<Self, E>;
+}
+
+macro_rules! impl_checked_math {
+    ($($t:ty),*) => {
+        $(
+            impl CheckedMath for $t {
+                fn checked_add_or<E>(self, rhs: Self, err: E) -> Result<Self, E> {
+                    self.checked_add(rhs).ok_or(err)
+                }
+
+                fn checked_sub_or<E>(self, rhs: Self, err: E) -> Result<Self, E> {
+                    self.checked_sub(rhs).ok_or(err)
+                }
+
+                fn checked_mul_or<E>(self, rhs: Self, err: E) -> Result<Self, E> {
+                    self.checked_mul(rhs).ok_or(err)
+                }
+            }
+        )*
+    };
+}
+
+impl_checked_math!(u64, u128);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checked_add_or() {
+        assert_eq!(u64::MAX.checked_add_or(0, "overflow"), Ok(u64::MAX));
+        assert_eq!((u64::MAX - 1).checked_add_or(1, "overflow"), Ok(u64::MAX));
+        assert_eq!(u64::MAX.checked_add_or(1, "overflow"), Err("overflow"));
+    }
+
+    #[test]
+    fn test_checked_sub_or() {
+        assert_eq!(13u128.checked_sub_or(13, "underflow"), Ok(0));
+        assert_eq!(0u128.checked_sub_or(0, "underflow"), Ok(0));
+        assert_eq!(12u128.checked_sub_or(13, "underflow"), Err("underflow"));
+    }
+
+    #[test]
+    fn test_checked_mul_or() {
+        assert_eq!(0u128.checked_mul_or(u128::MAX, "overflow"), Ok(0));
+        assert_eq!(
+            (u64::MAX / 2).checked_mul_or(2, "overflow"),
+            Ok(u64::MAX - 1)
+        );
+        assert_eq!(
+            (u64::MAX / 2 + 1).checked_mul_or(2, "overflow"),
+            Err("overflow")
+        );
+    }
+}
@@ -0,0 +1,418 @@
+//! Bodies of the owner messages and the pool metadata
+
+use ink_lang::codegen::{EmitEvent as _, Env as _};
+use ink_prelude::string::String;
+
+use crate::errors::{Error, Result};
+use crate::slushie::{
+    BuildInfo, EmergencyPaused, PoseidonHash, Slushie, TreeHasher, EVENT_VERSION,
+    STORAGE_LAYOUT_VERSION, TREE_HASHER_KIND,
+};
+use crate::tree::hasher::MerkleTreeHasher;
+use crate::tree::merkle_tree::MAX_DEPTH;
+use crate::{AccountId, BlockNumber};
+
+/// Git commit the contract is built from, passed in `SLUSHIE_GIT_COMMIT`
+const BUILD_GIT_COMMIT: Option<&str> = option_env!("SLUSHIE_GIT_COMMIT");
+
+/// Cargo features reported by `get_build_info` when enabled
+const BUILD_FEATURES: [(&str, bool); 7] = [
+    ("std", cfg!(feature = "std")),
+    ("hasher-blake", cfg!(feature = "hasher-blake")),
+    ("hasher-poseidon", cfg!(feature = "hasher-poseidon")),
+    (
+        "contract-hasher-poseidon",
+        cfg!(feature = "contract-hasher-poseidon"),
+    ),
+    (
+        "contract-hasher-blake",
+        cfg!(feature = "contract-hasher-blake"),
+    ),
+    ("ink-as-dependency", cfg!(feature = "ink-as-dependency")),
+    ("dev-assertions", cfg!(feature = "dev-assertions")),
+];
+
+impl Slushie {
+    /// Build metadata of the compiled code
+    pub(crate) fn build_info() -> BuildInfo {
+        BuildInfo {
+            crate_version: String::from(env!("CARGO_PKG_VERSION")),
+            git_commit: Self::git_commit(BUILD_GIT_COMMIT),
+            features: BUILD_FEATURES
+                .iter()
+                .filter(|(_, enabled)| *enabled)
+                .map(|(feature, _)| String::from(*feature))
+                .collect(),
+            hasher: TREE_HASHER_KIND,
+            max_depth: MAX_DEPTH as u32,
+            protocol_version: crate::protocol_constants::PROTOCOL_VERSION,
+        }
+    }
+
+    /// Git commit passed at build time, an unset or blank value is unknown
+    pub(crate) fn git_commit(commit: Option<&str>) -> Option<String> {
+        commit
+            .map(str::trim)
+            .filter(|commit| !commit.is_empty())
+            .map(String::from)
+    }
+
+    /// Top of the ZEROS ladder built from the zero leaf of the table by
+    /// hashing every level with itself
+    ///
+    /// Panics if it differs from the top of the table: constructors can't
+    /// return a Result, so the instantiation is aborted.
+    pub(crate) fn zeros_ladder_top(zeros: &[PoseidonHash; MAX_DEPTH]) -> PoseidonHash {
+        let [zero_leaf, .., table_top] = zeros;
+        let top = (1..MAX_DEPTH).fold(*zero_leaf, |zero, _| {
+            TreeHasher::hash_left_right(zero, zero)
+        });
+
+        assert!(
+            top == *table_top,
+            "ZEROS ladder of the merkle_tree hasher is inconsistent"
+        );
+
+        top
+    }
+
+    /// Whether the circuit breaker can be set up with the parameters
+    pub(crate) fn is_valid_circuit_breaker(
+        max_outflow_percent: u8,
+        window_blocks: BlockNumber,
+    ) -> bool {
+        max_outflow_percent == 0 || (max_outflow_percent <= 100 && window_blocks > 0)
+    }
+
+    /// Validate and store the circuit breaker parameters, see
+    /// `set_circuit_breaker`
+    pub(crate) fn configure_circuit_breaker(
+        &mut self,
+        max_outflow_percent: u8,
+        window_blocks: BlockNumber,
+    ) -> Result<()> {
+        self.ensure_layout()?;
+        self.ensure_owner()?;
+
+        if !Self::is_valid_circuit_breaker(max_outflow_percent, window_blocks) {
+            return Err(Error::InvalidCircuitBreaker);
+        }
+
+        self.max_outflow_percent = max_outflow_percent;
+        self.outflow_window_blocks = window_blocks;
+        self.clear_outflow();
+
+        Ok(())
+    }
+
+    /// Resume withdrawals and forget the outflow, see `reset_circuit_breaker`
+    pub(crate) fn clear_circuit_breaker(&mut self) -> Result<()> {
+        self.ensure_layout()?;
+        self.ensure_owner()?;
+
+        self.circuit_breaker_tripped = false;
+        self.clear_outflow();
+
+        Ok(())
+    }
+
+    /// Store the stats subscriber, see `set_stats_subscriber`
+    pub(crate) fn configure_stats_subscriber(
+        &mut self,
+        subscriber: Option<AccountId>,
+    ) -> Result<()> {
+        self.ensure_layout()?;
+        self.ensure_owner()?;
+
+        self.stats_subscriber = subscriber;
+
+        Ok(())
+    }
+
+    /// Migrate the storage from the stored layout version, see `migrate`
+    pub(crate) fn migrate_storage(&mut self) -> Result<()> {
+        self.ensure_owner()?;
+
+        match self.storage_layout_version {
+            STORAGE_LAYOUT_VERSION => Ok(()),
+            // no known layout to migrate from yet
+            _ => Err(Error::LayoutMismatch),
+        }
+    }
+
+    /// Pause withdrawals if the accounting is violated, see
+    /// `report_accounting_violation`
+    pub(crate) fn pause_on_accounting_violation(&mut self) -> Result<()> {
+        self.ensure_layout()?;
+
+        if self.withdrawals_paused {
+            return Err(Error::WithdrawalsPaused);
+        }
+
+        let violated = self.withdrawn_count > self.merkle_tree.next_index
+            || self.env().balance() < self.pending_payouts;
+        if !violated {
+            return Err(Error::NoAccountingViolation);
+        }
+
+        self.withdrawals_paused = true;
+
+        self.env().emit_event(EmergencyPaused {
+            version: EVENT_VERSION,
+            withdrawn_count: self.withdrawn_count,
+            deposit_count: self.merkle_tree.next_index,
+        });
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BUILD_GIT_COMMIT;
+    use crate::testing::*;
+
+    /// Imports `ink_lang` so we can use `#[ink::test]`.
+    use ink_lang as ink;
+
+    /// - the stats count deposits and successful withdrawals only
+    #[ink::test]
+    fn get_stats_works() {
+        let deposit_size = 13;
+        let mut slushie: Slushie = Slushie::new(deposit_size);
+
+        let mut model = Model::default();
+
+        deposit_checked(&mut slushie, &mut model, [1; 32]);
+        let root = deposit_checked(&mut slushie, &mut model, [2; 32]);
+        withdraw_checked(&mut slushie, &mut model, [3; 32], root);
+
+        assert!(slushie.withdraw([3; 32], root).is_err());
+        assert_invariants(&slushie, &mut model);
+
+        assert_eq!(
+            slushie.get_stats(),
+            PoolStats {
+                deposit_count: 2,
+                withdrawn_count: 1,
+                withdrawals_paused: false,
+            }
+        );
+    }
+
+    /// - a withdrawal which would violate the accounting only fails, as the
+    ///   failure is reverted on-chain; reporting a violated accounting
+    ///   pauses withdrawals for good
+    #[ink::test]
+    fn accounting_violation_pauses_withdrawals() {
+        let deposit_size = 13;
+        let mut slushie: Slushie = Slushie::new(deposit_size);
+
+        ink_env::test::set_value_transferred::<ink_env::DefaultEnvironment>(deposit_size);
+        let root = slushie.deposit([1; 32]).unwrap();
+        ink_env::test::set_value_transferred::<ink_env::DefaultEnvironment>(0);
+        assert_eq!(
+            slushie.report_accounting_violation(),
+            Err(Error::NoAccountingViolation)
+        );
+
+        slushie.__set_withdrawn_count(1);
+        let events = ink_env::test::recorded_events().count();
+
+        assert_eq!(
+            slushie.withdraw([2; 32], root),
+            Err(Error::AccountingViolation)
+        );
+        assert!(!slushie.get_stats().withdrawals_paused);
+        assert_eq!(ink_env::test::recorded_events().count(), events);
+
+        // as many withdrawals as deposits is consistent
+        assert_eq!(
+            slushie.report_accounting_violation(),
+            Err(Error::NoAccountingViolation)
+        );
+
+        slushie.__set_withdrawn_count(2);
+        assert_eq!(slushie.report_accounting_violation(), Ok(()));
+        assert!(slushie.get_stats().withdrawals_paused);
+
+        let event = ink_env::test::recorded_events().last().unwrap();
+        assert_eq!(event.topics, vec![signature_topic(EMERGENCY_PAUSED_EVENT)]);
+        let decoded = <EmergencyPaused as Decode>::decode(&mut &event.data[1..]).unwrap();
+        assert_eq!((decoded.withdrawn_count, decoded.deposit_count), (2, 1));
+
+        assert_eq!(
+            slushie.report_accounting_violation(),
+            Err(Error::WithdrawalsPaused)
+        );
+
+        slushie.__set_withdrawn_count(0);
+        let mut model = Model {
+            deposits: vec![[1; 32]],
+            paused: true,
+            ..Default::default()
+        };
+        assert_invariants(&slushie, &mut model);
+
+        assert_eq!(
+            slushie.withdraw([2; 32], root),
+            Err(Error::WithdrawalsPaused)
+        );
+        assert_eq!(
+            slushie.check_withdraw([2; 32], root),
+            Err(Error::WithdrawalsPaused)
+        );
+        assert_invariants(&slushie, &mut model);
+    }
+
+    /// - recorded payouts the balance doesn't cover are a reportable violation
+    #[ink::test]
+    fn uncovered_payouts_are_reportable() {
+        let deposit_size = 13;
+        let mut slushie: Slushie = Slushie::new_with_options(
+            deposit_size,
+            PoolOptions {
+                pull_payments: true,
+                ..Default::default()
+            },
+        );
+        let contract = ink_env::test::callee::<ink_env::DefaultEnvironment>();
+
+        let mut model = Model::default();
+        let root = deposit_checked(&mut slushie, &mut model, [1; 32]);
+        withdraw_checked(&mut slushie, &mut model, [2; 32], root);
+        assert_eq!(
+            slushie.report_accounting_violation(),
+            Err(Error::NoAccountingViolation)
+        );
+
+        ink_env::test::set_account_balance::<ink_env::DefaultEnvironment>(
+            contract,
+            deposit_size - 1,
+        );
+        assert_eq!(slushie.report_accounting_violation(), Ok(()));
+        model.paused = true;
+        assert_invariants(&slushie, &mut model);
+    }
+
+    /// - only the owner can set up the breaker, with valid parameters
+    #[ink::test]
+    fn set_circuit_breaker_works() {
+        let accounts = ink_env::test::default_accounts::<ink_env::DefaultEnvironment>();
+        let (mut slushie, root, mut model) = pool_with_circuit_breaker(0, 0, 2);
+
+        withdraw_checked(&mut slushie, &mut model, [101; 32], root);
+        assert_eq!(
+            slushie.get_circuit_breaker(),
+            CircuitBreakerStatus {
+                max_outflow_percent: 0,
+                window_blocks: 0,
+                tripped: false,
+                outflow: 0,
+            }
+        );
+
+        assert_eq!(
+            slushie.set_circuit_breaker(101, 10),
+            Err(Error::InvalidCircuitBreaker)
+        );
+        assert_eq!(
+            slushie.set_circuit_breaker(30, 0),
+            Err(Error::InvalidCircuitBreaker)
+        );
+
+        ink_env::test::set_caller::<Environment>(accounts.bob);
+        assert_eq!(
+            slushie.set_circuit_breaker(30, 10),
+            Err(Error::CallerIsNotOwner)
+        );
+
+        ink_env::test::set_caller::<Environment>(accounts.alice);
+        assert_eq!(slushie.set_circuit_breaker(30, 10), Ok(()));
+
+        // the last deposit left is all of the principal
+        withdraw_checked(&mut slushie, &mut model, [102; 32], root);
+        assert!(slushie.get_circuit_breaker().tripped);
+
+        assert_eq!(slushie.set_circuit_breaker(0, 0), Ok(()));
+        assert_eq!(slushie.reset_circuit_breaker(), Ok(()));
+        assert!(!slushie.get_circuit_breaker().tripped);
+        assert_invariants(&slushie, &mut model);
+    }
+
+    /// - the build info reports the compile-time values
+    #[ink::test]
+    fn get_build_info_works() {
+        let slushie: Slushie = Slushie::new(13);
+        let info = slushie.get_build_info();
+
+        assert_eq!(info.crate_version, env!("CARGO_PKG_VERSION"));
+        assert_eq!(info.git_commit, Slushie::git_commit(BUILD_GIT_COMMIT));
+        assert_eq!(info.hasher, TREE_HASHER_KIND);
+        assert_eq!(info.max_depth, MAX_DEPTH as u32);
+        assert_eq!(
+            info.protocol_version,
+            crate::protocol_constants::PROTOCOL_VERSION
+        );
+        assert_eq!(
+            info.features.contains(&String::from("std")),
+            cfg!(feature = "std")
+        );
+    }
+
+    /// - an unset or blank git commit is reported as unknown
+    #[test]
+    fn build_git_commit_handles_missing_values() {
+        assert_eq!(Slushie::git_commit(None), None);
+        assert_eq!(Slushie::git_commit(Some("")), None);
+        assert_eq!(Slushie::git_commit(Some(" \n")), None);
+        assert_eq!(
+            Slushie::git_commit(Some("8c06f8c\n")),
+            Some(String::from("8c06f8c"))
+        );
+    }
+
+    /// - the ZEROS ladder self-check accepts the compiled-in table
+    #[test]
+    fn zeros_ladder_self_check() {
+        assert_eq!(
+            Slushie::zeros_ladder_top(&TreeHasher::ZEROS),
+            TreeHasher::ZEROS[MAX_DEPTH - 1]
+        );
+    }
+
+    /// - the ZEROS ladder self-check aborts on a wrong zero leaf
+    #[test]
+    #[should_panic(expected = "ZEROS ladder of the merkle_tree hasher is inconsistent")]
+    fn zeros_ladder_self_check_rejects_a_corrupted_leaf() {
+        let mut corrupted = TreeHasher::ZEROS;
+        corrupted[0][31] ^= 1;
+
+        Slushie::zeros_ladder_top(&corrupted);
+    }
+
+    /// - the ZEROS ladder self-check aborts on a wrong top of the table
+    #[test]
+    #[should_panic(expected = "ZEROS ladder of the merkle_tree hasher is inconsistent")]
+    fn zeros_ladder_self_check_rejects_a_corrupted_top() {
+        let mut corrupted = TreeHasher::ZEROS;
+        corrupted[MAX_DEPTH - 1][0] ^= 1;
+
+        Slushie::zeros_ladder_top(&corrupted);
+    }
+
+    /// - only the owner can migrate, an up to date storage is left as is
+    #[ink::test]
+    fn migrate_works() {
+        let accounts = ink_env::test::default_accounts::<ink_env::DefaultEnvironment>();
+        ink_env::test::set_caller::<Environment>(accounts.alice);
+        let mut slushie: Slushie = Slushie::new(13);
+
+        ink_env::test::set_caller::<Environment>(accounts.bob);
+        assert_eq!(slushie.migrate(), Err(Error::CallerIsNotOwner));
+
+        ink_env::test::set_caller::<Environment>(accounts.alice);
+        assert_eq!(slushie.migrate(), Ok(()));
+        assert_eq!(slushie.storage_layout_version, STORAGE_LAYOUT_VERSION);
+    }
+}
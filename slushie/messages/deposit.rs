@@ -0,0 +1,475 @@
+//! Bodies of the deposit messages
+//!
+//! The messages stay in the `#[ink::contract]` module, which has to hold all of
+//! them, and call the helpers here.
+
+use ink_lang::codegen::{EmitEvent as _, Env as _};
+
+use crate::errors::{Error, Result};
+use crate::slushie::{
+    CommitmentInfo, Deposited, DepositedFor, Memo, PoseidonHash, Slushie, EVENT_VERSION,
+};
+use crate::subscriber::PoolEventKind;
+use crate::{AccountId, Timestamp};
+
+impl Slushie {
+    /// Insert the paid commitment and emit `Deposited`, shared by
+    /// `deposit` and `deposit_for`
+    pub(crate) fn insert_deposit(&mut self, commitment: PoseidonHash) -> Result<PoseidonHash> {
+        self.ensure_layout()?;
+
+        if self.env().transferred_value() != self.deposit_size {
+            return Err(Error::InvalidTransferredAmount);
+        }
+
+        self.validate_deposit(commitment)?;
+
+        let leaf_index = u32::try_from(self.merkle_tree.insert(commitment)?)
+            .map_err(|_| Error::MerkleTreeIsFull)?;
+        let root = self.merkle_tree.get_last_root() as PoseidonHash;
+        let timestamp = self.env().block_timestamp();
+
+        self.commitments.insert(commitment, &leaf_index);
+
+        if self.track_deposit_times {
+            self.deposit_times.insert(leaf_index, &timestamp);
+        }
+
+        if self.store_insertion_roots {
+            self.insertion_roots.insert(leaf_index, &root);
+        }
+
+        self.env().emit_event(Deposited {
+            version: EVENT_VERSION,
+            commitment,
+            timestamp,
+        });
+        self.notify_subscriber(PoolEventKind::Deposit);
+
+        Ok(root)
+    }
+
+    /// `deposit_for`: insert the deposit, then emit `DepositedFor` unless
+    /// the caller is the beneficiary
+    pub(crate) fn insert_deposit_for(
+        &mut self,
+        commitment: PoseidonHash,
+        beneficiary: AccountId,
+        memo: Option<Memo>,
+    ) -> Result<PoseidonHash> {
+        let root = self.insert_deposit(commitment)?;
+
+        if self.env().caller() != beneficiary {
+            self.env().emit_event(DepositedFor {
+                version: EVENT_VERSION,
+                commitment,
+                beneficiary,
+                memo,
+            });
+        }
+
+        Ok(root)
+    }
+
+    /// Checks shared by `deposit` and `can_deposit`
+    pub(crate) fn validate_deposit(&self, commitment: PoseidonHash) -> Result<()> {
+        if self.commitments.get(commitment).is_some() {
+            return Err(Error::DuplicateCommitment);
+        }
+
+        if self.merkle_tree.is_full() {
+            return Err(Error::MerkleTreeIsFull);
+        }
+
+        Ok(())
+    }
+
+    /// Inclusion data of the deposited commitment, see `get_commitment_info`
+    pub(crate) fn commitment_info(&self, commitment: PoseidonHash) -> Option<CommitmentInfo> {
+        let leaf_index = self.commitments.get(commitment)?;
+
+        Some(CommitmentInfo {
+            leaf_index,
+            deposit_timestamp: self.deposit_times.get(leaf_index),
+            root_at_insertion: self.insertion_roots.get(leaf_index),
+        })
+    }
+
+    /// Deposit timestamp by the leaf index, see `get_deposit_time`
+    pub(crate) fn deposit_time(&self, leaf_index: u32) -> Result<Option<Timestamp>> {
+        self.ensure_layout()?;
+
+        if !self.track_deposit_times {
+            return Err(Error::FeatureDisabled);
+        }
+
+        Ok(self.deposit_times.get(leaf_index))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::*;
+
+    /// Imports `ink_lang` so we can use `#[ink::test]`.
+    use ink_lang as ink;
+
+    /// can deposit funds with a proper `deposit_size`
+    #[ink::test]
+    fn deposit_works() {
+        let accounts = ink_env::test::default_accounts::<ink_env::DefaultEnvironment>();
+        let mut slushie: Slushie = Slushie::new(13);
+        let commitment: PoseidonHash =
+            hex!("0001020304050607 08090a0b0c0d0e0f 0001020304050607 08090a0b0c0d0e0f");
+
+        let initial_root_hash = slushie.get_root_hash();
+
+        ink_env::test::set_caller::<Environment>(accounts.bob);
+        ink_env::test::set_value_transferred::<ink_env::DefaultEnvironment>(13);
+        let res = slushie.deposit(commitment);
+        assert!(res.is_ok());
+
+        let resulting_root_hash = slushie.get_root_hash();
+        assert_ne!(initial_root_hash, resulting_root_hash);
+
+        let mut model = Model {
+            deposits: vec![commitment],
+            ..Default::default()
+        };
+        assert_invariants(&slushie, &mut model);
+    }
+
+    /// can't deposit funds with an invalid `deposit_size`
+    #[ink::test]
+    fn deposit_invalid_amount_fails() {
+        let accounts = ink_env::test::default_accounts::<ink_env::DefaultEnvironment>();
+        let deposit_size = 13;
+        let invalid_deposit_size = 55;
+        let mut slushie: Slushie = Slushie::new(deposit_size);
+        let commitment: PoseidonHash =
+            hex!("0001020304050607 08090a0b0c0d0e0f 0001020304050607 08090a0b0c0d0e0f");
+
+        let initial_root_hash = slushie.get_root_hash();
+
+        ink_env::test::set_caller::<Environment>(accounts.bob);
+        ink_env::test::set_value_transferred::<ink_env::DefaultEnvironment>(invalid_deposit_size);
+        let res = slushie.deposit(commitment);
+        assert_eq!(res.unwrap_err(), Error::InvalidTransferredAmount);
+
+        let resulting_root_hash = slushie.get_root_hash();
+        assert_eq!(initial_root_hash, resulting_root_hash);
+    }
+
+    // can't deposit funds if account doesn't have enough money
+    //
+    // this case shouldn't be tested cause is a pallete, which
+    // checks the sufficient amount of funds
+
+    /// - `Deposited` is published with the signature and the commitment topics
+    #[ink::test]
+    fn deposit_event_topics() {
+        let deposit_size = 13;
+        let mut slushie: Slushie = Slushie::new(deposit_size);
+        let commitment: PoseidonHash = [1; 32];
+
+        ink_env::test::set_value_transferred::<ink_env::DefaultEnvironment>(deposit_size);
+        slushie.deposit(commitment).unwrap();
+
+        assert_eq!(
+            last_event_topics(),
+            vec![
+                signature_topic(DEPOSITED_EVENT),
+                event_topic(b"Slushie::Deposited::commitment", &commitment),
+            ]
+        );
+    }
+
+    /// - `Deposited` data layout is pinned: event index, version, commitment, timestamp
+    #[ink::test]
+    fn deposit_event_layout() {
+        let deposit_size = 13;
+        let mut slushie: Slushie = Slushie::new(deposit_size);
+
+        ink_env::test::set_value_transferred::<ink_env::DefaultEnvironment>(deposit_size);
+        slushie.deposit([1; 32]).unwrap();
+
+        let timestamp = ink_env::block_timestamp::<ink_env::DefaultEnvironment>();
+        let expected = [
+            &[0u8, EVENT_VERSION][..],
+            &[1; 32],
+            &timestamp.to_le_bytes(),
+        ]
+        .concat();
+
+        let event = ink_env::test::recorded_events().last().unwrap();
+        assert_eq!(event.data, expected);
+    }
+
+    /// - can_deposit agrees with deposit
+    #[ink::test]
+    fn can_deposit_works() {
+        let deposit_size = 13;
+        let mut slushie: Slushie = Slushie::new(deposit_size);
+        let commitment: PoseidonHash = [1; 32];
+
+        assert_eq!(slushie.can_deposit(commitment), Ok(()));
+
+        ink_env::test::set_value_transferred::<ink_env::DefaultEnvironment>(deposit_size);
+        slushie.deposit(commitment).unwrap();
+
+        assert_eq!(
+            slushie.can_deposit(commitment),
+            Err(Error::DuplicateCommitment)
+        );
+
+        slushie.__set_next_index(2u64.pow(MAX_DEPTH as u32));
+        assert_eq!(slushie.can_deposit([2; 32]), Err(Error::MerkleTreeIsFull));
+        assert_eq!(slushie.deposit([2; 32]), Err(Error::MerkleTreeIsFull));
+    }
+
+    /// - a front-run deposit of the same commitment makes the original deposit
+    ///   fail in the pre-check, and leaves exactly one leaf for the commitment
+    #[ink::test]
+    fn front_run_deposit_fails_early() {
+        let accounts = ink_env::test::default_accounts::<ink_env::DefaultEnvironment>();
+        let deposit_size = 13;
+        let mut slushie: Slushie = Slushie::new(deposit_size);
+        let commitment: PoseidonHash = [1; 32];
+
+        assert_eq!(slushie.can_deposit(commitment), Ok(()));
+
+        ink_env::test::set_caller::<Environment>(accounts.eve);
+        ink_env::test::set_value_transferred::<ink_env::DefaultEnvironment>(deposit_size);
+        let root = slushie.deposit(commitment).unwrap();
+
+        ink_env::test::set_caller::<Environment>(accounts.bob);
+        assert_eq!(
+            slushie.can_deposit(commitment),
+            Err(Error::DuplicateCommitment)
+        );
+        assert_eq!(slushie.deposit(commitment), Err(Error::DuplicateCommitment));
+
+        assert_eq!(slushie.get_root_hash(), root);
+        assert_eq!(slushie.merkle_tree.next_index, 1);
+        assert_eq!(slushie.get_commitment_index(commitment), Some(0));
+    }
+
+    /// - can't deposit the same commitment twice
+    #[ink::test]
+    fn deposit_duplicate_commitment_fails() {
+        let deposit_size = 13;
+        let mut slushie: Slushie = Slushie::new(deposit_size);
+        let commitment: PoseidonHash =
+            hex!("0001020304050607 08090a0b0c0d0e0f 0001020304050607 08090a0b0c0d0e0f");
+
+        ink_env::test::set_value_transferred::<ink_env::DefaultEnvironment>(deposit_size);
+        let res = slushie.deposit(commitment);
+        assert!(res.is_ok());
+
+        let root_hash = slushie.get_root_hash();
+
+        let res = slushie.deposit(commitment);
+        assert_eq!(res.unwrap_err(), Error::DuplicateCommitment);
+        assert_eq!(slushie.get_root_hash(), root_hash);
+    }
+
+    /// - can get the index and info of a deposited commitment
+    #[ink::test]
+    fn get_commitment_info_works() {
+        let deposit_size = 13;
+        let mut slushie: Slushie = Slushie::new_with_options(
+            deposit_size,
+            PoolOptions {
+                store_insertion_roots: true,
+                track_deposit_times: true,
+                ..Default::default()
+            },
+        );
+        let first: PoseidonHash = [1; 32];
+        let second: PoseidonHash = [2; 32];
+
+        ink_env::test::set_value_transferred::<ink_env::DefaultEnvironment>(deposit_size);
+        slushie.deposit(first).unwrap();
+        ink_env::test::advance_block::<ink_env::DefaultEnvironment>();
+        let timestamp = ink_env::block_timestamp::<ink_env::DefaultEnvironment>();
+        let root = slushie.deposit(second).unwrap();
+
+        assert_eq!(slushie.get_commitment_index(first), Some(0));
+        assert_eq!(slushie.get_commitment_index(second), Some(1));
+        assert_eq!(
+            slushie.get_commitment_info(second),
+            Some(CommitmentInfo {
+                leaf_index: 1,
+                deposit_timestamp: Some(timestamp),
+                root_at_insertion: Some(root),
+            })
+        );
+    }
+
+    /// - commitment info is empty for unknown commitments
+    #[ink::test]
+    fn get_commitment_info_for_absent_commitment() {
+        let slushie: Slushie = Slushie::new(13);
+        let commitment: PoseidonHash = [1; 32];
+
+        assert_eq!(slushie.get_commitment_index(commitment), None);
+        assert_eq!(slushie.get_commitment_info(commitment), None);
+    }
+
+    /// - root at insertion isn't stored unless enabled in the constructor
+    #[ink::test]
+    fn get_commitment_info_without_insertion_roots() {
+        let deposit_size = 13;
+        let mut slushie: Slushie = Slushie::new(deposit_size);
+        let commitment: PoseidonHash = [1; 32];
+
+        ink_env::test::set_value_transferred::<ink_env::DefaultEnvironment>(deposit_size);
+        slushie.deposit(commitment).unwrap();
+
+        let info = slushie.get_commitment_info(commitment).unwrap();
+        assert_eq!(info.leaf_index, 0);
+        assert_eq!(info.deposit_timestamp, None);
+        assert_eq!(info.root_at_insertion, None);
+    }
+
+    /// - can get the deposit time by the leaf index when it's tracked
+    #[ink::test]
+    fn get_deposit_time_works() {
+        let deposit_size = 13;
+        let mut slushie: Slushie = Slushie::new_with_options(
+            deposit_size,
+            PoolOptions {
+                track_deposit_times: true,
+                ..Default::default()
+            },
+        );
+
+        ink_env::test::advance_block::<ink_env::DefaultEnvironment>();
+        let timestamp = ink_env::block_timestamp::<ink_env::DefaultEnvironment>();
+
+        ink_env::test::set_value_transferred::<ink_env::DefaultEnvironment>(deposit_size);
+        slushie.deposit([1; 32]).unwrap();
+
+        assert_eq!(slushie.get_deposit_time(0), Ok(Some(timestamp)));
+        assert_eq!(slushie.get_deposit_time(1), Ok(None));
+    }
+
+    /// - can't get the deposit time when it isn't tracked
+    #[ink::test]
+    fn get_deposit_time_disabled() {
+        let deposit_size = 13;
+        let mut slushie: Slushie = Slushie::new(deposit_size);
+
+        ink_env::test::set_value_transferred::<ink_env::DefaultEnvironment>(deposit_size);
+        slushie.deposit([1; 32]).unwrap();
+
+        assert_eq!(slushie.deposit_times.get(0), None);
+        assert_eq!(slushie.get_deposit_time(0), Err(Error::FeatureDisabled));
+    }
+
+    /// - commitment info is kept after its root is rotated out of the history
+    #[ink::test]
+    fn get_commitment_info_after_root_rotation() {
+        let deposit_size = 13;
+        let mut slushie: Slushie = Slushie::new_with_options(
+            deposit_size,
+            PoolOptions {
+                store_insertion_roots: true,
+                ..Default::default()
+            },
+        );
+
+        ink_env::test::set_value_transferred::<ink_env::DefaultEnvironment>(deposit_size);
+        let first_root = slushie.deposit([0; 32]).unwrap();
+
+        for i in 1..=DEFAULT_ROOT_HISTORY_SIZE {
+            slushie.deposit([i as u8; 32]).unwrap();
+        }
+
+        assert!(!slushie.merkle_tree.is_known_root(first_root));
+
+        let info = slushie.get_commitment_info([0; 32]).unwrap();
+        assert_eq!(info.leaf_index, 0);
+        assert_eq!(info.root_at_insertion, Some(first_root));
+    }
+
+    /// - a deposit on behalf of another account emits `Deposited` and `DepositedFor`
+    #[ink::test]
+    fn deposit_for_works() {
+        let accounts = ink_env::test::default_accounts::<ink_env::DefaultEnvironment>();
+        let deposit_size = 13;
+        let mut slushie: Slushie = Slushie::new(deposit_size);
+        let commitment: PoseidonHash = [1; 32];
+        let memo: Memo = [7; 32];
+
+        ink_env::test::set_caller::<Environment>(accounts.bob);
+        ink_env::test::set_value_transferred::<ink_env::DefaultEnvironment>(deposit_size);
+        let root = slushie
+            .deposit_for(commitment, accounts.charlie, Some(memo))
+            .unwrap();
+
+        assert_eq!(root, slushie.get_root_hash());
+        assert_eq!(slushie.get_commitment_index(commitment), Some(0));
+
+        let events = events_after_instantiation();
+        assert_eq!(events.len(), 2);
+        assert_eq!(
+            events[0].topics,
+            vec![
+                signature_topic(DEPOSITED_EVENT),
+                event_topic(b"Slushie::Deposited::commitment", &commitment),
+            ]
+        );
+        assert_eq!(
+            events[1].topics,
+            vec![
+                signature_topic(DEPOSITED_FOR_EVENT),
+                event_topic(b"Slushie::DepositedFor::commitment", &commitment),
+                encoded_into_hash(&ink_env::topics::PrefixedValue {
+                    prefix: b"Slushie::DepositedFor::beneficiary",
+                    value: &accounts.charlie,
+                }),
+            ]
+        );
+
+        let decoded = <DepositedFor as Decode>::decode(&mut &events[1].data[1..]).unwrap();
+        assert_eq!(decoded.beneficiary, accounts.charlie);
+        assert_eq!(decoded.memo, Some(memo));
+
+        let mut model = Model {
+            deposits: vec![commitment],
+            ..Default::default()
+        };
+        assert_invariants(&slushie, &mut model);
+    }
+
+    /// - a deposit for oneself emits only `Deposited`
+    #[ink::test]
+    fn deposit_for_self_emits_deposited_only() {
+        let accounts = ink_env::test::default_accounts::<ink_env::DefaultEnvironment>();
+        let deposit_size = 13;
+        let mut slushie: Slushie = Slushie::new(deposit_size);
+
+        ink_env::test::set_caller::<Environment>(accounts.bob);
+        ink_env::test::set_value_transferred::<ink_env::DefaultEnvironment>(deposit_size);
+        slushie.deposit_for([1; 32], accounts.bob, None).unwrap();
+
+        assert_eq!(events_after_instantiation().len(), 1);
+        assert_eq!(last_event_topics()[0], signature_topic(DEPOSITED_EVENT));
+    }
+
+    /// - a deposit on behalf of another account runs the `deposit` checks
+    #[ink::test]
+    fn deposit_for_invalid_amount_fails() {
+        let accounts = ink_env::test::default_accounts::<ink_env::DefaultEnvironment>();
+        let deposit_size = 13;
+        let mut slushie: Slushie = Slushie::new(deposit_size);
+
+        ink_env::test::set_value_transferred::<ink_env::DefaultEnvironment>(deposit_size + 1);
+        assert_eq!(
+            slushie.deposit_for([1; 32], accounts.charlie, None),
+            Err(Error::InvalidTransferredAmount)
+        );
+        assert_eq!(events_after_instantiation().len(), 0);
+    }
+}
@@ -0,0 +1,9 @@
+//! Bodies of the contract messages, grouped like the ABI
+//!
+//! `#[ink::contract]` in ink! 3 only accepts an inline module, so every
+//! message has to be declared there. The messages are thin wrappers around
+//! the inherent `Slushie` methods and plain functions of these modules.
+
+pub(crate) mod admin;
+pub(crate) mod deposit;
+pub(crate) mod withdraw;
@@ -0,0 +1,1110 @@
+//! Bodies of the withdrawal messages, the payouts and the circuit breaker
+//!
+//! The outflow window arithmetic works on plain values, the methods pass in
+//! the block number and the stored counters.
+
+use ink_lang::codegen::{EmitEvent as _, Env as _};
+use ink_prelude::vec::Vec;
+
+use crate::errors::{Error, Result};
+use crate::math::CheckedMath;
+use crate::slushie::{
+    CircuitBreakerStatus, CircuitBreakerTripped, Payout, PayoutClaimed, PoseidonHash, RootStatus,
+    Slushie, TreeHasher, WithdrawalReceipt, Withdrawn, EVENT_VERSION, MAX_NULLIFIER_BATCH,
+};
+use crate::subscriber::PoolEventKind;
+use crate::tree::hasher::MerkleTreeHasher;
+use crate::tree::merkle_tree::MAX_DEPTH;
+use crate::{AccountId, Balance, BlockNumber};
+
+/// Index of the fixed outflow window the block falls into
+pub(crate) fn window_index(block_number: BlockNumber, window_blocks: BlockNumber) -> BlockNumber {
+    block_number.checked_div(window_blocks).unwrap_or_default()
+}
+
+/// Outflow of `window` and of the window before it, given the counters
+/// stored for `stored_window`
+///
+/// The stored counters are reset lazily: they are shifted or dropped once
+/// the block moves past their windows.
+pub(crate) fn shift_outflows(
+    window: BlockNumber,
+    stored_window: BlockNumber,
+    stored_current: Balance,
+    stored_previous: Balance,
+) -> (Balance, Balance) {
+    if window == stored_window {
+        (stored_current, stored_previous)
+    } else if Some(window) == stored_window.checked_add(1) {
+        (0, stored_current)
+    } else {
+        (0, 0)
+    }
+}
+
+/// Outflow within the last `window_blocks` blocks as of the block, given
+/// the outflow of the current and the previous fixed window
+///
+/// The previous window is weighted by the share of it the sliding window
+/// still covers, as the outflow within a window isn't tracked by block.
+/// Without the weight a burst at the end of a window would count for up
+/// to two windows.
+pub(crate) fn sliding_outflow(
+    current: Balance,
+    previous: Balance,
+    block_number: BlockNumber,
+    window_blocks: BlockNumber,
+) -> Result<Balance> {
+    let elapsed = block_number.checked_rem(window_blocks).unwrap_or_default();
+    let covered = Balance::from(window_blocks.saturating_sub(elapsed));
+
+    let weighted = previous
+        .checked_mul_or(covered, Error::AccountingViolation)?
+        .checked_div(Balance::from(window_blocks))
+        .unwrap_or_default();
+
+    current.checked_add_or(weighted, Error::AccountingViolation)
+}
+
+/// Whether the outflow is more than `max_outflow_percent` of the principal
+pub(crate) fn is_outflow_exceeded(
+    outflow: Balance,
+    principal: Balance,
+    max_outflow_percent: u8,
+) -> Result<bool> {
+    Ok(outflow.checked_mul_or(100, Error::AccountingViolation)?
+        > principal.checked_mul_or(
+            Balance::from(max_outflow_percent),
+            Error::AccountingViolation,
+        )?)
+}
+
+impl Slushie {
+    /// `withdraw` after the checks pass: pay out or record the payout, spend
+    /// the nullifier and count the outflow
+    pub(crate) fn execute_withdrawal(
+        &mut self,
+        nullifier_hash: PoseidonHash,
+        root: PoseidonHash,
+    ) -> Result<WithdrawalReceipt> {
+        // a violation can't pause withdrawals here, the failed message is
+        // reverted, see `report_accounting_violation`
+        self.validate_withdraw(nullifier_hash, root)?;
+
+        let recipient = self.env().caller();
+
+        if self.pull_payments {
+            self.record_payout(nullifier_hash, recipient)?;
+        } else if self.env().transfer(recipient, self.deposit_size).is_err() {
+            return Err(Error::InvalidDepositSize);
+        }
+
+        self.used_nullifiers.insert(nullifier_hash, &true);
+        self.withdrawn_count = self
+            .withdrawn_count
+            .checked_add_or(1, Error::AccountingViolation)?;
+        let tripped = self.record_outflow()?;
+
+        let timestamp = self.env().block_timestamp();
+
+        self.env().emit_event(Withdrawn {
+            version: EVENT_VERSION,
+            nullifier_hash,
+            timestamp,
+        });
+
+        if let Some(event) = tripped {
+            self.env().emit_event(event);
+        }
+        self.notify_subscriber(PoolEventKind::Withdrawal);
+
+        Ok(WithdrawalReceipt {
+            nullifier_hash,
+            recipient,
+            amount: self.deposit_size,
+            root,
+            timestamp,
+        })
+    }
+
+    /// Checks shared by `withdraw` and `check_withdraw`, see `withdraw`
+    /// for their order
+    pub(crate) fn validate_withdraw(
+        &self,
+        nullifier_hash: PoseidonHash,
+        root: PoseidonHash,
+    ) -> Result<()> {
+        self.ensure_layout()?;
+
+        if self.withdrawals_paused {
+            return Err(Error::WithdrawalsPaused);
+        }
+
+        if self.circuit_breaker_tripped {
+            return Err(Error::CircuitBreakerTripped);
+        }
+
+        if self.env().transferred_value() != 0 {
+            return Err(Error::UnexpectedTransferredValue);
+        }
+
+        if self.used_nullifiers.get(nullifier_hash).is_some() {
+            return Err(Error::NullifierAlreadyUsed);
+        }
+
+        // the initial root of an empty pool is shared by all pools with the
+        // same tree, so a withdrawal from another pool could be replayed
+        // against it. It fills the whole roots history on instantiation and
+        // stays known until the first ROOT_HISTORY_SIZE deposits replace it,
+        // so it's rejected regardless of the deposits made.
+        let is_empty_pool_root = root == TreeHasher::ZEROS[MAX_DEPTH - 1];
+
+        if is_empty_pool_root || !self.merkle_tree.is_known_root(root) {
+            return Err(Error::UnknownRoot);
+        }
+
+        // every withdrawal spends a note of a distinct leaf, so there can
+        // never be more withdrawals than leaves
+        if self.withdrawn_count >= self.merkle_tree.next_index {
+            return Err(Error::AccountingViolation);
+        }
+
+        // recorded payouts are owed to their recipients already
+        self.env()
+            .balance()
+            .checked_sub_or(self.pending_payouts, Error::InsufficientFunds)?
+            .checked_sub_or(self.deposit_size, Error::InsufficientFunds)?;
+
+        Ok(())
+    }
+
+    /// Record the payout of a withdrawal for `claim_payout`
+    pub(crate) fn record_payout(
+        &mut self,
+        nullifier_hash: PoseidonHash,
+        recipient: AccountId,
+    ) -> Result<()> {
+        self.pending_payouts = self
+            .pending_payouts
+            .checked_add_or(self.deposit_size, Error::AccountingViolation)?;
+        self.payouts.insert(
+            nullifier_hash,
+            &Payout {
+                recipient,
+                amount: self.deposit_size,
+            },
+        );
+
+        Ok(())
+    }
+
+    /// Pay out and remove the recorded payout, see `claim_payout`
+    pub(crate) fn pay_out(&mut self, nullifier_hash: PoseidonHash) -> Result<Payout> {
+        self.ensure_layout()?;
+
+        if !self.pull_payments {
+            return Err(Error::FeatureDisabled);
+        }
+
+        if self.env().transferred_value() != 0 {
+            return Err(Error::UnexpectedTransferredValue);
+        }
+
+        let payout = self
+            .payouts
+            .get(nullifier_hash)
+            .ok_or(Error::PayoutNotFound)?;
+
+        self.pending_payouts = self
+            .pending_payouts
+            .checked_sub_or(payout.amount, Error::AccountingViolation)?;
+        self.payouts.remove(nullifier_hash);
+
+        if self
+            .env()
+            .transfer(payout.recipient, payout.amount)
+            .is_err()
+        {
+            return Err(Error::InvalidDepositSize);
+        }
+
+        self.env().emit_event(PayoutClaimed {
+            version: EVENT_VERSION,
+            nullifier_hash,
+            recipient: payout.recipient,
+            amount: payout.amount,
+        });
+
+        Ok(payout)
+    }
+
+    /// Count a withdrawal of `deposit_size` into the outflow window, after
+    /// the withdrawal itself was counted in `withdrawn_count`
+    ///
+    /// Returns the event to emit if the withdrawal trips the breaker.
+    pub(crate) fn record_outflow(&mut self) -> Result<Option<CircuitBreakerTripped>> {
+        if self.max_outflow_percent == 0 {
+            return Ok(None);
+        }
+
+        let (window, current, previous) = self.window_outflows();
+        let current = current.checked_add_or(self.deposit_size, Error::AccountingViolation)?;
+        let outflow = sliding_outflow(
+            current,
+            previous,
+            self.env().block_number(),
+            self.outflow_window_blocks,
+        )?;
+
+        self.outflow_window = window;
+        self.outflow_current = current;
+        self.outflow_previous = previous;
+
+        let deposits_left = self
+            .merkle_tree
+            .next_index
+            .checked_sub_or(self.withdrawn_count, Error::AccountingViolation)?;
+        let principal = Balance::from(deposits_left)
+            .checked_mul_or(self.deposit_size, Error::AccountingViolation)?
+            .checked_add_or(outflow, Error::AccountingViolation)?;
+
+        if !is_outflow_exceeded(outflow, principal, self.max_outflow_percent)? {
+            return Ok(None);
+        }
+
+        self.circuit_breaker_tripped = true;
+
+        Ok(Some(CircuitBreakerTripped {
+            version: EVENT_VERSION,
+            outflow,
+            principal,
+        }))
+    }
+
+    /// Index of the outflow window of the current block
+    pub(crate) fn outflow_window_index(&self) -> BlockNumber {
+        window_index(self.env().block_number(), self.outflow_window_blocks)
+    }
+
+    /// Current window index with the outflow of the current and the
+    /// previous window, see `shift_outflows`
+    pub(crate) fn window_outflows(&self) -> (BlockNumber, Balance, Balance) {
+        let window = self.outflow_window_index();
+        let (current, previous) = shift_outflows(
+            window,
+            self.outflow_window,
+            self.outflow_current,
+            self.outflow_previous,
+        );
+
+        (window, current, previous)
+    }
+
+    /// Forget the outflow so far, e.g. after the parameters change
+    pub(crate) fn clear_outflow(&mut self) {
+        self.outflow_window = self.outflow_window_index();
+        self.outflow_current = 0;
+        self.outflow_previous = 0;
+    }
+
+    /// Circuit breaker parameters and state, see `get_circuit_breaker`
+    pub(crate) fn circuit_breaker_status(&self) -> CircuitBreakerStatus {
+        let (_, current, previous) = self.window_outflows();
+
+        CircuitBreakerStatus {
+            max_outflow_percent: self.max_outflow_percent,
+            window_blocks: self.outflow_window_blocks,
+            tripped: self.circuit_breaker_tripped,
+            outflow: sliding_outflow(
+                current,
+                previous,
+                self.env().block_number(),
+                self.outflow_window_blocks,
+            )
+            .unwrap_or(Balance::MAX),
+        }
+    }
+
+    /// Spent state of each nullifier hash, see `are_nullifiers_used`
+    pub(crate) fn nullifiers_used(&self, nullifier_hashes: Vec<PoseidonHash>) -> Result<Vec<bool>> {
+        self.ensure_layout()?;
+
+        if nullifier_hashes.len() > MAX_NULLIFIER_BATCH {
+            return Err(Error::BatchTooLarge);
+        }
+
+        Ok(nullifier_hashes
+            .into_iter()
+            .map(|nullifier_hash| self.used_nullifiers.get(nullifier_hash).is_some())
+            .collect())
+    }
+
+    /// Position of the root in the roots history, see `get_root_status`
+    pub(crate) fn root_status(&self, root: PoseidonHash) -> RootStatus {
+        match self.merkle_tree.root_age(root) {
+            Some(0) => RootStatus::Current,
+            Some(age) => RootStatus::Historical { age: age as u32 },
+            None => RootStatus::Unknown,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_outflow_exceeded, shift_outflows, sliding_outflow, window_index};
+    use crate::testing::*;
+
+    /// Imports `ink_lang` so we can use `#[ink::test]`.
+    use ink_lang as ink;
+
+    /// - blocks fall into fixed windows, a disabled breaker has none
+    #[test]
+    fn window_index_works() {
+        assert_eq!(window_index(0, 10), 0);
+        assert_eq!(window_index(9, 10), 0);
+        assert_eq!(window_index(10, 10), 1);
+        assert_eq!(window_index(10, 0), 0);
+    }
+
+    /// - the stored counters are kept within their window, become the
+    ///   previous outflow in the next one and are dropped after that
+    #[test]
+    fn shift_outflows_works() {
+        assert_eq!(shift_outflows(4, 4, 13, 26), (13, 26));
+        assert_eq!(shift_outflows(5, 4, 13, 26), (0, 13));
+        assert_eq!(shift_outflows(6, 4, 13, 26), (0, 0));
+        assert_eq!(shift_outflows(0, BlockNumber::MAX, 13, 26), (0, 0));
+    }
+
+    /// - the previous window counts by the share the sliding window still covers
+    #[test]
+    fn sliding_outflow_weights_the_previous_window() {
+        assert_eq!(sliding_outflow(13, 20, 10, 10), Ok(33));
+        assert_eq!(sliding_outflow(13, 20, 15, 10), Ok(23));
+        assert_eq!(sliding_outflow(13, 20, 19, 10), Ok(15));
+        assert_eq!(sliding_outflow(13, 20, 19, 0), Ok(13));
+        assert_eq!(
+            sliding_outflow(Balance::MAX, 20, 10, 10),
+            Err(Error::AccountingViolation)
+        );
+    }
+
+    /// - the limit itself isn't exceeded yet
+    #[test]
+    fn is_outflow_exceeded_works() {
+        assert_eq!(is_outflow_exceeded(50, 100, 50), Ok(false));
+        assert_eq!(is_outflow_exceeded(51, 100, 50), Ok(true));
+        assert_eq!(is_outflow_exceeded(1, 100, 0), Ok(true));
+        assert_eq!(
+            is_outflow_exceeded(Balance::MAX, 100, 50),
+            Err(Error::AccountingViolation)
+        );
+    }
+
+    /// - can withdraw funds with a proper deposit_size and hash
+    #[ink::test]
+    fn withdraw_works() {
+        let accounts = ink_env::test::default_accounts::<ink_env::DefaultEnvironment>();
+        let deposit_size: Balance = 13;
+        let mut slushie: Slushie = Slushie::new(deposit_size);
+        let hash: PoseidonHash =
+            hex!("0001020304050607 08090a0b0c0d0e0f 0001020304050607 08090a0b0c0d0e0f");
+
+        ink_env::test::set_caller::<Environment>(accounts.alice);
+        ink_env::test::set_value_transferred::<ink_env::DefaultEnvironment>(deposit_size);
+        let res = slushie.deposit(hash);
+        assert!(res.is_ok());
+
+        let resulting_root_hash = slushie.get_root_hash();
+
+        ink_env::test::set_value_transferred::<ink_env::DefaultEnvironment>(0);
+        let res = slushie.withdraw(hash, resulting_root_hash);
+        assert_eq!(
+            res.unwrap(),
+            WithdrawalReceipt {
+                nullifier_hash: hash,
+                recipient: accounts.alice,
+                amount: deposit_size,
+                root: resulting_root_hash,
+                timestamp: ink_env::block_timestamp::<ink_env::DefaultEnvironment>(),
+            }
+        );
+
+        let mut model = Model {
+            deposits: vec![hash],
+            withdrawals: vec![hash],
+            ..Default::default()
+        };
+        assert_invariants(&slushie, &mut model);
+    }
+
+    /// - can withdraw funds with a proper deposit_size and hash by different account
+    #[ink::test]
+    fn withdraw_from_different_account_works() {
+        let accounts = ink_env::test::default_accounts::<ink_env::DefaultEnvironment>();
+        let deposit_size = 13;
+        let mut slushie: Slushie = Slushie::new(deposit_size);
+        let hash: PoseidonHash =
+            hex!("0001020304050607 08090a0b0c0d0e0f 0001020304050607 08090a0b0c0d0e0f");
+
+        ink_env::test::set_caller::<Environment>(accounts.alice);
+        ink_env::test::set_value_transferred::<ink_env::DefaultEnvironment>(deposit_size);
+        let res = slushie.deposit(hash);
+        assert!(res.is_ok());
+
+        let resulting_root_hash = slushie.get_root_hash();
+
+        ink_env::test::set_caller::<Environment>(accounts.eve);
+        ink_env::test::set_value_transferred::<ink_env::DefaultEnvironment>(0);
+        let res = slushie.withdraw(hash, resulting_root_hash);
+        let receipt = res.unwrap();
+        assert_eq!(receipt.recipient, accounts.eve);
+        assert_eq!(receipt.amount, deposit_size);
+    }
+
+    /// - can't withdraw funds with invalid root hash
+    #[ink::test]
+    fn withdraw_with_invalid_root_fails() {
+        let accounts = ink_env::test::default_accounts::<ink_env::DefaultEnvironment>();
+        let deposit_size = 13;
+        let mut slushie: Slushie = Slushie::new(deposit_size);
+        let hash: PoseidonHash =
+            hex!("0001020304050607 08090a0b0c0d0e0f 0001020304050607 08090a0b0c0d0e0f");
+
+        ink_env::test::set_caller::<Environment>(accounts.alice);
+        ink_env::test::set_value_transferred::<ink_env::DefaultEnvironment>(deposit_size);
+        let res = slushie.deposit(hash);
+        assert!(res.is_ok());
+
+        let invalid_root_hash: PoseidonHash =
+            hex!("0000000000000000 0000000000000000 0001020304050607 08090a0b0c0d0e0f");
+
+        ink_env::test::set_value_transferred::<ink_env::DefaultEnvironment>(0);
+        let res = slushie.withdraw(hash, invalid_root_hash);
+        assert_eq!(res.unwrap_err(), Error::UnknownRoot);
+    }
+
+    /// - can't double withdraw funds with a proper deposit_size and a valid hash
+    #[ink::test]
+    fn withdraw_with_used_nullifier_fails() {
+        let accounts = ink_env::test::default_accounts::<ink_env::DefaultEnvironment>();
+        let deposit_size = 13;
+        let mut slushie: Slushie = Slushie::new(deposit_size);
+        let hash: PoseidonHash =
+            hex!("0001020304050607 08090a0b0c0d0e0f 0001020304050607 08090a0b0c0d0e0f");
+
+        ink_env::test::set_caller::<Environment>(accounts.alice);
+        ink_env::test::set_value_transferred::<ink_env::DefaultEnvironment>(deposit_size);
+        let res = slushie.deposit(hash);
+        assert!(res.is_ok());
+        let resulting_root_hash = slushie.get_root_hash();
+
+        ink_env::test::set_value_transferred::<ink_env::DefaultEnvironment>(0);
+        let res = slushie.withdraw(hash, resulting_root_hash);
+        assert!(res.is_ok());
+
+        let mut model = Model {
+            deposits: vec![hash],
+            withdrawals: vec![hash],
+            ..Default::default()
+        };
+        assert_invariants(&slushie, &mut model);
+
+        let res = slushie.withdraw(hash, resulting_root_hash);
+        assert_eq!(res.unwrap_err(), Error::NullifierAlreadyUsed);
+        assert_invariants(&slushie, &mut model);
+    }
+
+    /// - `Withdrawn` is published with the signature and the nullifier hash topics
+    #[ink::test]
+    fn withdraw_event_topics() {
+        let deposit_size = 13;
+        let mut slushie: Slushie = Slushie::new(deposit_size);
+        let nullifier_hash: PoseidonHash = [2; 32];
+
+        ink_env::test::set_value_transferred::<ink_env::DefaultEnvironment>(deposit_size);
+        let root = slushie.deposit([1; 32]).unwrap();
+        ink_env::test::set_value_transferred::<ink_env::DefaultEnvironment>(0);
+        slushie.withdraw(nullifier_hash, root).unwrap();
+
+        assert_eq!(
+            last_event_topics(),
+            vec![
+                signature_topic(WITHDRAWN_EVENT),
+                event_topic(b"Slushie::Withdrawn::nullifier_hash", &nullifier_hash),
+            ]
+        );
+    }
+
+    /// - for a note with commitment = H(nullifier, secret) and
+    ///   nullifier_hash = H(nullifier, nullifier) the value in `Withdrawn` is
+    ///   never the deposited commitment, so the events can't be linked by it
+    #[ink::test]
+    fn withdrawn_event_never_carries_the_commitment() {
+        let deposit_size = 13;
+        let mut slushie: Slushie = Slushie::new(deposit_size);
+        let mut rng = rng();
+
+        for _ in 0..8 {
+            let (nullifier, secret) = (random_hash(&mut rng), random_hash(&mut rng));
+            let commitment = TreeHasher::hash_left_right(nullifier, secret);
+            let nullifier_hash = TreeHasher::hash_left_right(nullifier, nullifier);
+
+            ink_env::test::set_value_transferred::<ink_env::DefaultEnvironment>(deposit_size);
+            let root = slushie.deposit(commitment).unwrap();
+            ink_env::test::set_value_transferred::<ink_env::DefaultEnvironment>(0);
+            slushie.withdraw(nullifier_hash, root).unwrap();
+
+            let event = ink_env::test::recorded_events().last().unwrap();
+            let withdrawn = <Withdrawn as Decode>::decode(&mut &event.data[1..]).unwrap();
+
+            assert_eq!(withdrawn.nullifier_hash, nullifier_hash);
+            assert_ne!(withdrawn.nullifier_hash, commitment);
+            assert!(!event.topics.contains(&event_topic(
+                b"Slushie::Withdrawn::nullifier_hash",
+                &commitment
+            )));
+        }
+    }
+
+    /// - a duplicate withdrawal is rejected by the nullifier check before the root check
+    #[ink::test]
+    fn withdraw_checks_nullifier_before_root() {
+        let deposit_size = 13;
+        let mut slushie: Slushie = Slushie::new(deposit_size);
+        let hash: PoseidonHash = [1; 32];
+
+        ink_env::test::set_value_transferred::<ink_env::DefaultEnvironment>(deposit_size);
+        let root = slushie.deposit(hash).unwrap();
+        ink_env::test::set_value_transferred::<ink_env::DefaultEnvironment>(0);
+        slushie.withdraw(hash, root).unwrap();
+
+        let unknown_root: PoseidonHash = [9; 32];
+        let res = slushie.withdraw(hash, unknown_root);
+        assert_eq!(res.unwrap_err(), Error::NullifierAlreadyUsed);
+    }
+
+    /// - can't withdraw with value attached, the value would be stranded in the contract
+    #[ink::test]
+    fn withdraw_with_transferred_value_fails() {
+        let deposit_size = 13;
+        let mut slushie: Slushie = Slushie::new(deposit_size);
+        let hash: PoseidonHash = [1; 32];
+
+        ink_env::test::set_value_transferred::<ink_env::DefaultEnvironment>(deposit_size);
+        let root = slushie.deposit(hash).unwrap();
+
+        ink_env::test::set_value_transferred::<ink_env::DefaultEnvironment>(1);
+        let res = slushie.withdraw(hash, root);
+        assert_eq!(res.unwrap_err(), Error::UnexpectedTransferredValue);
+        assert_eq!(slushie.used_nullifiers.get(hash), None);
+
+        ink_env::test::set_value_transferred::<ink_env::DefaultEnvironment>(0);
+        let res = slushie.withdraw(hash, root);
+        assert!(res.is_ok());
+    }
+
+    /// - can withdraw when the contract balance is exactly the deposit_size,
+    ///   can't withdraw when it's lower
+    #[ink::test]
+    fn withdraw_balance_boundary() {
+        let deposit_size = 13;
+        let mut slushie: Slushie = Slushie::new(deposit_size);
+        let contract = ink_env::test::callee::<ink_env::DefaultEnvironment>();
+
+        ink_env::test::set_value_transferred::<ink_env::DefaultEnvironment>(deposit_size);
+        let root = slushie.deposit([1; 32]).unwrap();
+        ink_env::test::set_value_transferred::<ink_env::DefaultEnvironment>(0);
+
+        ink_env::test::set_account_balance::<ink_env::DefaultEnvironment>(
+            contract,
+            deposit_size - 1,
+        );
+        let res = slushie.withdraw([1; 32], root);
+        assert_eq!(res.unwrap_err(), Error::InsufficientFunds);
+
+        ink_env::test::set_account_balance::<ink_env::DefaultEnvironment>(contract, deposit_size);
+        let res = slushie.withdraw([1; 32], root);
+        assert!(res.is_ok());
+    }
+
+    /// - in the pull payments mode the withdrawal only records the payout,
+    ///   the claim pays it and removes it, so it can't be claimed twice
+    #[ink::test]
+    fn pull_payments_withdraw_and_claim() {
+        let deposit_size = 13;
+        let accounts = ink_env::test::default_accounts::<ink_env::DefaultEnvironment>();
+        let mut slushie: Slushie = Slushie::new_with_options(
+            deposit_size,
+            PoolOptions {
+                pull_payments: true,
+                ..Default::default()
+            },
+        );
+        let balance = |account| {
+            ink_env::test::get_account_balance::<ink_env::DefaultEnvironment>(account).unwrap()
+        };
+
+        ink_env::test::set_value_transferred::<ink_env::DefaultEnvironment>(deposit_size);
+        let root = slushie.deposit([1; 32]).unwrap();
+        ink_env::test::set_value_transferred::<ink_env::DefaultEnvironment>(0);
+
+        ink_env::test::set_caller::<Environment>(accounts.bob);
+        let bob_balance = balance(accounts.bob);
+        let receipt = slushie.withdraw([2; 32], root).unwrap();
+        assert_eq!(receipt.amount, deposit_size);
+        assert_eq!(balance(accounts.bob), bob_balance);
+
+        let mut model = Model {
+            deposits: vec![[1; 32]],
+            withdrawals: vec![[2; 32]],
+            unclaimed: vec![[2; 32]],
+            ..Default::default()
+        };
+        assert_invariants(&slushie, &mut model);
+
+        let payout = Payout {
+            recipient: accounts.bob,
+            amount: deposit_size,
+        };
+        assert_eq!(slushie.get_payout([2; 32]), Some(payout.clone()));
+        assert_eq!(slushie.pending_payouts, deposit_size);
+
+        assert_eq!(slushie.claim_payout([2; 32]), Ok(payout));
+        assert_eq!(balance(accounts.bob), bob_balance + deposit_size);
+        assert_eq!(slushie.get_payout([2; 32]), None);
+        assert_eq!(slushie.pending_payouts, 0);
+        model.unclaimed.clear();
+        assert_invariants(&slushie, &mut model);
+
+        assert_eq!(slushie.claim_payout([2; 32]), Err(Error::PayoutNotFound));
+        assert_invariants(&slushie, &mut model);
+    }
+
+    /// - anyone can claim a payout, the tokens go to the recorded recipient
+    #[ink::test]
+    fn claim_payout_of_someone_else_pays_the_recipient() {
+        let deposit_size = 13;
+        let accounts = ink_env::test::default_accounts::<ink_env::DefaultEnvironment>();
+        let mut slushie: Slushie = Slushie::new_with_options(
+            deposit_size,
+            PoolOptions {
+                pull_payments: true,
+                ..Default::default()
+            },
+        );
+        let balance = |account| {
+            ink_env::test::get_account_balance::<ink_env::DefaultEnvironment>(account).unwrap()
+        };
+
+        let mut model = Model::default();
+        let root = deposit_checked(&mut slushie, &mut model, [1; 32]);
+
+        ink_env::test::set_caller::<Environment>(accounts.bob);
+        withdraw_checked(&mut slushie, &mut model, [2; 32], root);
+
+        ink_env::test::set_caller::<Environment>(accounts.eve);
+        let (bob_balance, eve_balance) = (balance(accounts.bob), balance(accounts.eve));
+        slushie.claim_payout([2; 32]).unwrap();
+        model.unclaimed.clear();
+        assert_invariants(&slushie, &mut model);
+
+        assert_eq!(balance(accounts.bob), bob_balance + deposit_size);
+        assert_eq!(balance(accounts.eve), eve_balance);
+
+        let event = ink_env::test::recorded_events().last().unwrap();
+        assert_eq!(
+            event.topics,
+            vec![
+                signature_topic(PAYOUT_CLAIMED_EVENT),
+                event_topic(b"Slushie::PayoutClaimed::nullifier_hash", &[2; 32]),
+            ]
+        );
+        let decoded = <PayoutClaimed as Decode>::decode(&mut &event.data[1..]).unwrap();
+        assert_eq!(event.data[0], 5);
+        assert_eq!(decoded.recipient, accounts.bob);
+        assert_eq!(decoded.amount, deposit_size);
+    }
+
+    /// - the push payments mode pays inside `withdraw` and has nothing to claim
+    #[ink::test]
+    fn push_payments_mode_has_no_payouts() {
+        let deposit_size = 13;
+        let accounts = ink_env::test::default_accounts::<ink_env::DefaultEnvironment>();
+        let mut slushie: Slushie = Slushie::new(deposit_size);
+
+        ink_env::test::set_value_transferred::<ink_env::DefaultEnvironment>(deposit_size);
+        let root = slushie.deposit([1; 32]).unwrap();
+        ink_env::test::set_value_transferred::<ink_env::DefaultEnvironment>(0);
+
+        ink_env::test::set_caller::<Environment>(accounts.bob);
+        let bob_balance =
+            ink_env::test::get_account_balance::<ink_env::DefaultEnvironment>(accounts.bob)
+                .unwrap();
+        slushie.withdraw([2; 32], root).unwrap();
+
+        assert_eq!(
+            ink_env::test::get_account_balance::<ink_env::DefaultEnvironment>(accounts.bob),
+            Ok(bob_balance + deposit_size)
+        );
+        assert_eq!(slushie.get_payout([2; 32]), None);
+        assert_eq!(slushie.claim_payout([2; 32]), Err(Error::FeatureDisabled));
+    }
+
+    /// - recorded payouts are reserved, a withdrawal can't spend them
+    #[ink::test]
+    fn pending_payouts_are_reserved() {
+        let deposit_size = 13;
+        let mut slushie: Slushie = Slushie::new_with_options(
+            deposit_size,
+            PoolOptions {
+                pull_payments: true,
+                ..Default::default()
+            },
+        );
+        let contract = ink_env::test::callee::<ink_env::DefaultEnvironment>();
+
+        let mut model = Model::default();
+        deposit_checked(&mut slushie, &mut model, [1; 32]);
+        let root = deposit_checked(&mut slushie, &mut model, [2; 32]);
+
+        ink_env::test::set_account_balance::<ink_env::DefaultEnvironment>(
+            contract,
+            2 * deposit_size - 1,
+        );
+        withdraw_checked(&mut slushie, &mut model, [3; 32], root);
+        assert_eq!(
+            slushie.withdraw([4; 32], root),
+            Err(Error::InsufficientFunds)
+        );
+        assert_invariants(&slushie, &mut model);
+
+        ink_env::test::set_account_balance::<ink_env::DefaultEnvironment>(
+            contract,
+            2 * deposit_size,
+        );
+        withdraw_checked(&mut slushie, &mut model, [4; 32], root);
+    }
+
+    /// - can't withdraw against the initial root of an empty pool, even if
+    ///   the contract holds funds or deposits were made since
+    #[ink::test]
+    fn withdraw_from_empty_pool_fails() {
+        let deposit_size = 13;
+        let mut slushie: Slushie = Slushie::new(deposit_size);
+        let contract = ink_env::test::callee::<ink_env::DefaultEnvironment>();
+
+        ink_env::test::set_account_balance::<ink_env::DefaultEnvironment>(contract, deposit_size);
+        ink_env::test::set_value_transferred::<ink_env::DefaultEnvironment>(0);
+
+        let empty_root = slushie.get_root_hash();
+        assert_eq!(empty_root, TreeHasher::ZEROS[MAX_DEPTH - 1]);
+        assert!(slushie.merkle_tree.is_known_root(empty_root));
+
+        let res = slushie.withdraw([1; 32], empty_root);
+        assert_eq!(res.unwrap_err(), Error::UnknownRoot);
+
+        // still in the roots history after a deposit
+        ink_env::test::set_value_transferred::<ink_env::DefaultEnvironment>(deposit_size);
+        slushie.deposit([2; 32]).unwrap();
+        ink_env::test::set_value_transferred::<ink_env::DefaultEnvironment>(0);
+        assert!(slushie.merkle_tree.is_known_root(empty_root));
+
+        assert_eq!(
+            slushie.check_withdraw([1; 32], empty_root),
+            Err(Error::UnknownRoot)
+        );
+        let res = slushie.withdraw([1; 32], empty_root);
+        assert_eq!(res.unwrap_err(), Error::UnknownRoot);
+    }
+
+    /// - `check_withdraw` agrees with `withdraw` on every rejection and
+    ///   doesn't spend the nullifier
+    #[ink::test]
+    fn check_withdraw_agrees_with_withdraw() {
+        let deposit_size = 13;
+        let mut slushie: Slushie = Slushie::new(deposit_size);
+        let contract = ink_env::test::callee::<ink_env::DefaultEnvironment>();
+
+        // empty pool
+        ink_env::test::set_value_transferred::<ink_env::DefaultEnvironment>(0);
+        let empty_root = slushie.get_root_hash();
+        assert_eq!(
+            slushie.check_withdraw([1; 32], empty_root),
+            Err(Error::UnknownRoot)
+        );
+        assert_eq!(
+            slushie.check_withdraw([1; 32], empty_root),
+            slushie.withdraw([1; 32], empty_root).map(|_| ())
+        );
+
+        ink_env::test::set_value_transferred::<ink_env::DefaultEnvironment>(deposit_size);
+        let root = slushie.deposit([1; 32]).unwrap();
+        ink_env::test::set_value_transferred::<ink_env::DefaultEnvironment>(0);
+        slushie.withdraw([2; 32], root).unwrap();
+        ink_env::test::set_value_transferred::<ink_env::DefaultEnvironment>(deposit_size);
+        slushie.deposit([3; 32]).unwrap();
+
+        let cases: Vec<(Balance, Balance, PoseidonHash, PoseidonHash, Error)> = vec![
+            (
+                1,
+                deposit_size,
+                [1; 32],
+                root,
+                Error::UnexpectedTransferredValue,
+            ),
+            (0, deposit_size, [2; 32], root, Error::NullifierAlreadyUsed),
+            (0, deposit_size, [1; 32], [9; 32], Error::UnknownRoot),
+            (0, deposit_size - 1, [1; 32], root, Error::InsufficientFunds),
+        ];
+
+        for (transferred, balance, nullifier_hash, root, error) in cases {
+            ink_env::test::set_value_transferred::<ink_env::DefaultEnvironment>(transferred);
+            ink_env::test::set_account_balance::<ink_env::DefaultEnvironment>(contract, balance);
+
+            assert_eq!(slushie.check_withdraw(nullifier_hash, root), Err(error));
+            assert_eq!(
+                slushie.check_withdraw(nullifier_hash, root),
+                slushie.withdraw(nullifier_hash, root).map(|_| ())
+            );
+        }
+
+        ink_env::test::set_value_transferred::<ink_env::DefaultEnvironment>(0);
+        ink_env::test::set_account_balance::<ink_env::DefaultEnvironment>(contract, deposit_size);
+        assert_eq!(slushie.check_withdraw([1; 32], root), Ok(()));
+        assert_eq!(slushie.check_withdraw([1; 32], root), Ok(()));
+        assert!(slushie.withdraw([1; 32], root).is_ok());
+        assert_eq!(
+            slushie.check_withdraw([1; 32], root),
+            Err(Error::NullifierAlreadyUsed)
+        );
+    }
+
+    /// - the status of a root changes from current to historical to unknown
+    #[ink::test]
+    fn get_root_status_works() {
+        let deposit_size = 13;
+        let mut slushie: Slushie = Slushie::new(deposit_size);
+
+        ink_env::test::set_value_transferred::<ink_env::DefaultEnvironment>(deposit_size);
+        let root = slushie.deposit([0; 32]).unwrap();
+        assert_eq!(slushie.get_root_status(root), RootStatus::Current);
+        assert_eq!(slushie.get_root_status([9; 32]), RootStatus::Unknown);
+
+        slushie.deposit([1; 32]).unwrap();
+        assert_eq!(
+            slushie.get_root_status(root),
+            RootStatus::Historical { age: 1 }
+        );
+
+        for i in 2..DEFAULT_ROOT_HISTORY_SIZE {
+            slushie.deposit([i as u8; 32]).unwrap();
+        }
+        assert_eq!(
+            slushie.get_root_status(root),
+            RootStatus::Historical {
+                age: DEFAULT_ROOT_HISTORY_SIZE as u32 - 1
+            }
+        );
+
+        slushie
+            .deposit([DEFAULT_ROOT_HISTORY_SIZE as u8; 32])
+            .unwrap();
+        assert_eq!(slushie.get_root_status(root), RootStatus::Unknown);
+    }
+
+    /// - spent and unspent nullifiers are reported in order, duplicates included
+    #[ink::test]
+    fn are_nullifiers_used_works() {
+        let deposit_size = 13;
+        let mut slushie: Slushie = Slushie::new(deposit_size);
+
+        ink_env::test::set_value_transferred::<ink_env::DefaultEnvironment>(deposit_size);
+        let root = slushie.deposit([1; 32]).unwrap();
+        slushie.deposit([2; 32]).unwrap();
+        ink_env::test::set_value_transferred::<ink_env::DefaultEnvironment>(0);
+        slushie.withdraw([3; 32], root).unwrap();
+
+        assert_eq!(slushie.are_nullifiers_used(vec![]), Ok(vec![]));
+        assert_eq!(
+            slushie.are_nullifiers_used(vec![[4; 32], [3; 32], [4; 32], [3; 32]]),
+            Ok(vec![false, true, false, true])
+        );
+    }
+
+    /// - can't query more than `MAX_NULLIFIER_BATCH` nullifiers at once
+    #[ink::test]
+    fn are_nullifiers_used_batch_bound() {
+        let slushie: Slushie = Slushie::new(13);
+
+        assert_eq!(
+            slushie.are_nullifiers_used(vec![[1; 32]; MAX_NULLIFIER_BATCH]),
+            Ok(vec![false; MAX_NULLIFIER_BATCH])
+        );
+        assert_eq!(
+            slushie.are_nullifiers_used(vec![[1; 32]; MAX_NULLIFIER_BATCH + 1]),
+            Err(Error::BatchTooLarge)
+        );
+    }
+
+    /// - the withdrawal crossing the outflow limit succeeds and trips the
+    ///   breaker, later withdrawals fail until the owner resets it
+    #[ink::test]
+    fn circuit_breaker_trips_on_outflow() {
+        let accounts = ink_env::test::default_accounts::<ink_env::DefaultEnvironment>();
+        let (mut slushie, root, mut model) = pool_with_circuit_breaker(30, 10, 10);
+
+        // exactly 30% of the principal
+        for nullifier in 101..=103 {
+            withdraw_checked(&mut slushie, &mut model, [nullifier; 32], root);
+        }
+        assert!(!slushie.get_circuit_breaker().tripped);
+
+        withdraw_checked(&mut slushie, &mut model, [104; 32], root);
+        assert_eq!(
+            slushie.get_circuit_breaker(),
+            CircuitBreakerStatus {
+                max_outflow_percent: 30,
+                window_blocks: 10,
+                tripped: true,
+                outflow: 4 * 13,
+            }
+        );
+
+        let event = ink_env::test::recorded_events().last().unwrap();
+        assert_eq!(
+            event.topics,
+            vec![signature_topic(CIRCUIT_BREAKER_TRIPPED_EVENT)]
+        );
+        assert_eq!(event.data[0], 6);
+        let decoded = <CircuitBreakerTripped as Decode>::decode(&mut &event.data[1..]).unwrap();
+        assert_eq!(
+            (decoded.version, decoded.outflow, decoded.principal),
+            (EVENT_VERSION, 4 * 13, 10 * 13)
+        );
+
+        assert_eq!(
+            slushie.withdraw([105; 32], root),
+            Err(Error::CircuitBreakerTripped)
+        );
+        assert_eq!(
+            slushie.check_withdraw([105; 32], root),
+            Err(Error::CircuitBreakerTripped)
+        );
+
+        assert_invariants(&slushie, &mut model);
+
+        // deposits are unaffected
+        let root = deposit_checked(&mut slushie, &mut model, [11; 32]);
+
+        ink_env::test::set_caller::<Environment>(accounts.bob);
+        assert_eq!(
+            slushie.reset_circuit_breaker(),
+            Err(Error::CallerIsNotOwner)
+        );
+
+        ink_env::test::set_caller::<Environment>(accounts.alice);
+        assert_eq!(slushie.reset_circuit_breaker(), Ok(()));
+        assert_eq!(slushie.get_circuit_breaker().outflow, 0);
+        withdraw_checked(&mut slushie, &mut model, [105; 32], root);
+    }
+
+    /// - the outflow of the previous window fades out as the window slides
+    ///   past it, older outflow doesn't count
+    #[ink::test]
+    fn circuit_breaker_window_slides() {
+        let (mut slushie, root, mut model) = pool_with_circuit_breaker(30, 10, 10);
+        let advance_blocks = |blocks| {
+            for _ in 0..blocks {
+                ink_env::test::advance_block::<ink_env::DefaultEnvironment>();
+            }
+        };
+
+        for nullifier in 101..=103 {
+            withdraw_checked(&mut slushie, &mut model, [nullifier; 32], root);
+        }
+
+        advance_blocks(10);
+        assert_eq!(slushie.get_circuit_breaker().outflow, 3 * 13);
+
+        // half of the previous window, rounded down
+        advance_blocks(5);
+        assert_eq!(slushie.get_circuit_breaker().outflow, 3 * 13 / 2);
+
+        advance_blocks(5);
+        assert_eq!(slushie.get_circuit_breaker().outflow, 0);
+
+        // 2 of the 7 deposits left
+        for nullifier in 104..=105 {
+            withdraw_checked(&mut slushie, &mut model, [nullifier; 32], root);
+        }
+        assert!(!slushie.get_circuit_breaker().tripped);
+
+        // a fifth of the previous window is still covered, an outflow of 18
+        // of 4 * 13 + 18 doesn't trip, the previous window in full would
+        advance_blocks(18);
+        withdraw_checked(&mut slushie, &mut model, [106; 32], root);
+        assert_eq!(slushie.get_circuit_breaker().outflow, 13 + 2 * 13 / 5);
+        assert!(!slushie.get_circuit_breaker().tripped);
+
+        withdraw_checked(&mut slushie, &mut model, [107; 32], root);
+        assert!(slushie.get_circuit_breaker().tripped);
+    }
+
+    /// - withdrawals at a low velocity never trip the breaker
+    #[ink::test]
+    fn circuit_breaker_ignores_low_outflow() {
+        let (mut slushie, root, mut model) = pool_with_circuit_breaker(30, 10, 10);
+        let mut root = root;
+
+        for round in 0..50u8 {
+            withdraw_checked(&mut slushie, &mut model, [round; 32], root);
+
+            root = deposit_checked(&mut slushie, &mut model, [round.wrapping_add(100); 32]);
+
+            for _ in 0..10 {
+                ink_env::test::advance_block::<ink_env::DefaultEnvironment>();
+            }
+        }
+
+        assert!(!slushie.get_circuit_breaker().tripped);
+    }
+
+    /// - `Withdrawn` data layout is pinned: event index, version, nullifier hash, timestamp
+    #[ink::test]
+    fn withdraw_event_layout() {
+        let deposit_size = 13;
+        let mut slushie: Slushie = Slushie::new(deposit_size);
+
+        ink_env::test::set_value_transferred::<ink_env::DefaultEnvironment>(deposit_size);
+        let root = slushie.deposit([1; 32]).unwrap();
+        ink_env::test::set_value_transferred::<ink_env::DefaultEnvironment>(0);
+        slushie.withdraw([2; 32], root).unwrap();
+
+        let timestamp = ink_env::block_timestamp::<ink_env::DefaultEnvironment>();
+        let expected = [
+            &[1u8, EVENT_VERSION][..],
+            &[2; 32],
+            &timestamp.to_le_bytes(),
+        ]
+        .concat();
+
+        let event = ink_env::test::recorded_events().last().unwrap();
+        assert_eq!(event.data, expected);
+    }
+
+    /// - a root is withdrawable only while it's in the history
+    #[ink::test]
+    fn withdraw_against_forced_root() {
+        let deposit_size = 13;
+        let mut slushie: Slushie = Slushie::new(deposit_size);
+
+        ink_env::test::set_value_transferred::<ink_env::DefaultEnvironment>(deposit_size);
+        let root = slushie.deposit([1; 32]).unwrap();
+        slushie.__force_root([9; 32]);
+        ink_env::test::set_value_transferred::<ink_env::DefaultEnvironment>(0);
+
+        assert_eq!(slushie.withdraw([2; 32], root), Err(Error::UnknownRoot));
+        assert!(slushie.withdraw([2; 32], [9; 32]).is_ok());
+    }
+}
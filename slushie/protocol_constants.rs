@@ -0,0 +1,84 @@
+//! Constants which form the identity of the protocol
+//!
+//! Every deployment and every note depends on them: the ZEROS tables are derived
+//! from the seed, the roots from the depth, byte string hashes from the Poseidon
+//! packing and indexers look for the event signatures. They are pinned by a
+//! digest test, so a change has to update the digest and bump `PROTOCOL_VERSION`.
+//!
+//! The build script includes this file too, keep it free of crate dependencies.
+
+/// Version of the protocol constants, bumped with any change to them
+pub const PROTOCOL_VERSION: u32 = 3;
+
+/// Seed of the zero leaf, hashed into the first entry of every ZEROS table
+pub const ZEROS_SEED: &[u8] = b"slushie";
+
+/// Maximal depth of the merkle tree and the number of entries in a ZEROS table
+pub const MAX_DEPTH: usize = 32;
+
+/// Number of input bytes packed into one field element by `Poseidon::hash_bytes`
+pub const POSEIDON_CHUNK_SIZE: usize = 31;
+
+/// Byte appended to the input of `Poseidon::hash_bytes` before the zero padding
+pub const POSEIDON_PADDING_BYTE: u8 = 0x01;
+
+/// Event signatures, the first topic of every event
+pub const DEPOSITED_EVENT: &str = "Slushie::Deposited";
+pub const WITHDRAWN_EVENT: &str = "Slushie::Withdrawn";
+pub const DEPOSITED_FOR_EVENT: &str = "Slushie::DepositedFor";
+pub const EMERGENCY_PAUSED_EVENT: &str = "Slushie::EmergencyPaused";
+pub const POOL_INITIALIZED_EVENT: &str = "Slushie::PoolInitialized";
+pub const PAYOUT_CLAIMED_EVENT: &str = "Slushie::PayoutClaimed";
+pub const CIRCUIT_BREAKER_TRIPPED_EVENT: &str = "Slushie::CircuitBreakerTripped";
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use hex_literal::hex;
+    use ink_env::hash::{Blake2x256, CryptoHash};
+
+    /// Blake2x256 of the constants in `protocol_constants_bytes` order
+    const PROTOCOL_CONSTANTS_DIGEST: [u8; 32] =
+        hex!("0da6410f814bc02a ac67b87a9628cfe1 170d99d7d6ce6d19 46bf60cebfec385b");
+
+    /// All constants, byte strings prefixed with their length
+    fn protocol_constants_bytes() -> Vec<u8> {
+        let mut bytes = Vec::new();
+        let mut push_bytes = |value: &[u8]| {
+            bytes.extend_from_slice(&(value.len() as u32).to_le_bytes());
+            bytes.extend_from_slice(value);
+        };
+
+        push_bytes(&PROTOCOL_VERSION.to_le_bytes());
+        push_bytes(ZEROS_SEED);
+        push_bytes(&(MAX_DEPTH as u32).to_le_bytes());
+        push_bytes(&(POSEIDON_CHUNK_SIZE as u32).to_le_bytes());
+        push_bytes(&[POSEIDON_PADDING_BYTE]);
+
+        for event in [
+            DEPOSITED_EVENT,
+            WITHDRAWN_EVENT,
+            DEPOSITED_FOR_EVENT,
+            EMERGENCY_PAUSED_EVENT,
+            POOL_INITIALIZED_EVENT,
+            PAYOUT_CLAIMED_EVENT,
+            CIRCUIT_BREAKER_TRIPPED_EVENT,
+        ] {
+            push_bytes(event.as_bytes());
+        }
+
+        bytes
+    }
+
+    #[test]
+    fn test_protocol_constants_digest() {
+        let mut digest = [0; 32];
+        Blake2x256::hash(&protocol_constants_bytes(), &mut digest);
+
+        assert_eq!(
+            digest, PROTOCOL_CONSTANTS_DIGEST,
+            "protocol constants changed, bump PROTOCOL_VERSION and update the digest"
+        );
+    }
+}
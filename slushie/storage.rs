@@ -0,0 +1,174 @@
+//! Checks of the stored state shared by the messages, and the test hooks
+//! which manipulate it
+//!
+//! The storage struct itself has to stay in the `#[ink::contract]` module.
+
+use ink_lang::codegen::Env as _;
+
+use crate::errors::{Error, Result};
+#[cfg(test)]
+use crate::slushie::PoseidonHash;
+use crate::slushie::{Slushie, STORAGE_LAYOUT_VERSION};
+
+impl Slushie {
+    /// Check that the caller is the owner
+    pub(crate) fn ensure_owner(&self) -> Result<()> {
+        if self.env().caller() != self.owner {
+            return Err(Error::CallerIsNotOwner);
+        }
+
+        Ok(())
+    }
+
+    /// Check that the storage was written with the layout this code expects
+    pub(crate) fn ensure_layout(&self) -> Result<()> {
+        if self.storage_layout_version != STORAGE_LAYOUT_VERSION {
+            return Err(Error::LayoutMismatch);
+        }
+
+        Ok(())
+    }
+}
+
+/// State manipulation for the unit tests
+///
+/// Plain methods rather than messages: ink! 3 can't compile messages
+/// conditionally, and this way the hooks are never part of the ABI.
+#[cfg(test)]
+impl Slushie {
+    /// Set the index of the next leaf, e.g. to make the merkle_tree full
+    pub(crate) fn __set_next_index(&mut self, next_index: u64) {
+        self.merkle_tree.next_index = next_index;
+    }
+
+    /// Replace the last root of the merkle_tree
+    pub(crate) fn __force_root(&mut self, root: PoseidonHash) {
+        self.merkle_tree.force_last_root(root);
+    }
+
+    /// Set the number of successful withdrawals
+    pub(crate) fn __set_withdrawn_count(&mut self, withdrawn_count: u64) {
+        self.withdrawn_count = withdrawn_count;
+    }
+
+    /// Set the storage layout version, e.g. to simulate a missed migration
+    pub(crate) fn __set_storage_layout_version(&mut self, version: u32) {
+        self.storage_layout_version = version;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::*;
+
+    /// Imports `ink_lang` so we can use `#[ink::test]`.
+    use ink_lang as ink;
+
+    /// - a spent nullifier takes one storage cell, the same as it would with a
+    ///   truncated key, as mapping storage keys are hashes anyway
+    #[ink::test]
+    fn spent_nullifier_storage_footprint() {
+        let deposit_size = 13;
+        let mut slushie: Slushie = Slushie::new(deposit_size);
+        let contract = ink_env::test::callee::<ink_env::DefaultEnvironment>();
+        let used_cells = || {
+            ink_env::test::count_used_storage_cells::<ink_env::DefaultEnvironment>(&contract)
+                .unwrap()
+        };
+
+        ink_env::test::set_value_transferred::<ink_env::DefaultEnvironment>(deposit_size);
+        let root = slushie.deposit([1; 32]).unwrap();
+        ink_env::test::set_value_transferred::<ink_env::DefaultEnvironment>(0);
+
+        let cells = used_cells();
+        slushie.withdraw([2; 32], root).unwrap();
+        assert_eq!(used_cells(), cells + 1);
+
+        let mut truncated: ink_storage::Mapping<[u8; 16], bool> = Default::default();
+        let cells = used_cells();
+        truncated.insert([2; 16], &true);
+        assert_eq!(used_cells(), cells + 1);
+    }
+
+    /// - new storage entries per message, each of them needs a storage deposit:
+    ///   a deposit takes one plus one per enabled option, a withdrawal one
+    #[ink::test]
+    fn storage_entries_per_message() {
+        let deposit_size = 13;
+        let contract = ink_env::test::callee::<ink_env::DefaultEnvironment>();
+        // the account only shows up with its first storage entry
+        let used_cells = || {
+            ink_env::test::count_used_storage_cells::<ink_env::DefaultEnvironment>(&contract)
+                .unwrap_or(0)
+        };
+
+        let mut slushie: Slushie = Slushie::new(deposit_size);
+        ink_env::test::set_value_transferred::<ink_env::DefaultEnvironment>(deposit_size);
+        let cells = used_cells();
+        let root = slushie.deposit([1; 32]).unwrap();
+        assert_eq!(used_cells(), cells + 1);
+
+        let cells = used_cells();
+        slushie
+            .deposit_for([2; 32], AccountId::from([3; 32]), None)
+            .unwrap();
+        assert_eq!(used_cells(), cells + 1);
+
+        ink_env::test::set_value_transferred::<ink_env::DefaultEnvironment>(0);
+        let cells = used_cells();
+        slushie.withdraw([4; 32], root).unwrap();
+        assert_eq!(used_cells(), cells + 1);
+
+        let mut slushie: Slushie = Slushie::new_with_options(
+            deposit_size,
+            PoolOptions {
+                store_insertion_roots: true,
+                track_deposit_times: true,
+                ..Default::default()
+            },
+        );
+        ink_env::test::set_value_transferred::<ink_env::DefaultEnvironment>(deposit_size);
+        let cells = used_cells();
+        slushie.deposit([5; 32]).unwrap();
+        assert_eq!(used_cells(), cells + 3);
+    }
+
+    /// - the owner and the storage layout version are set on instantiation
+    #[ink::test]
+    fn constructor_sets_owner_and_layout_version() {
+        let accounts = ink_env::test::default_accounts::<ink_env::DefaultEnvironment>();
+        ink_env::test::set_caller::<Environment>(accounts.charlie);
+        let slushie: Slushie = Slushie::new(13);
+
+        assert_eq!(slushie.owner, accounts.charlie);
+        assert_eq!(slushie.storage_layout_version, STORAGE_LAYOUT_VERSION);
+    }
+
+    /// - fallible messages fail with `LayoutMismatch` until the storage is migrated
+    #[ink::test]
+    fn layout_mismatch_fails() {
+        let deposit_size = 13;
+        let mut slushie: Slushie = Slushie::new_with_options(
+            deposit_size,
+            PoolOptions {
+                track_deposit_times: true,
+                ..Default::default()
+            },
+        );
+        slushie.__set_storage_layout_version(STORAGE_LAYOUT_VERSION + 1);
+
+        ink_env::test::set_value_transferred::<ink_env::DefaultEnvironment>(deposit_size);
+        assert_eq!(slushie.deposit([1; 32]), Err(Error::LayoutMismatch));
+        assert_eq!(slushie.can_deposit([1; 32]), Err(Error::LayoutMismatch));
+        assert_eq!(slushie.get_deposit_time(0), Err(Error::LayoutMismatch));
+
+        ink_env::test::set_value_transferred::<ink_env::DefaultEnvironment>(0);
+        let root = slushie.get_root_hash();
+        assert_eq!(slushie.withdraw([2; 32], root), Err(Error::LayoutMismatch));
+        assert_eq!(slushie.migrate(), Err(Error::LayoutMismatch));
+        assert_eq!(
+            slushie.set_stats_subscriber(None),
+            Err(Error::LayoutMismatch)
+        );
+    }
+}
@@ -0,0 +1,230 @@
+//! Notifications of an analytics contract about the pool activity
+//!
+//! The owner can register a stats subscriber with `set_stats_subscriber`. It
+//! receives a `StatsSubscriber::notify` call after every deposit and withdrawal
+//! with aggregate data only, which the events publish anyway: the pool, the
+//! kind of the operation, the block timestamp and the counters. Commitments,
+//! nullifier hashes and recipients are never passed.
+//!
+//! The call is fire and forget. It runs after the state changes and the
+//! events, gets at most `SUBSCRIBER_GAS_LIMIT` gas and can't reenter the pool,
+//! as ink! calls don't allow reentrancy by default. Its result is ignored, so
+//! a faulty or trapping subscriber can't block deposits or withdrawals.
+
+use ink_lang::codegen::Env as _;
+
+use crate::slushie::Slushie;
+use crate::{AccountId, Environment, Timestamp};
+
+/// Kind of the pool operation a subscriber is notified about
+#[derive(Debug, Clone, Copy, PartialEq, Eq, scale::Encode, scale::Decode)]
+#[cfg_attr(feature = "std", derive(scale_info::TypeInfo))]
+pub enum PoolEventKind {
+    Deposit,
+    Withdrawal,
+}
+
+/// Aggregate data of a pool operation passed to the subscriber
+#[derive(Debug, Clone, PartialEq, Eq, scale::Encode, scale::Decode)]
+#[cfg_attr(feature = "std", derive(scale_info::TypeInfo))]
+pub struct PoolEvent {
+    /// Account of the pool contract
+    pub pool: AccountId,
+    pub kind: PoolEventKind,
+    /// Block timestamp of the operation
+    pub timestamp: Timestamp,
+    /// Deposits so far, including this one
+    pub deposit_count: u64,
+    /// Withdrawals so far, including this one
+    pub withdrawn_count: u64,
+}
+
+/// Interface of the stats subscriber contracts
+#[ink_lang::trait_definition]
+pub trait StatsSubscriber {
+    /// Handle an operation of the pool which calls it
+    #[ink(message)]
+    fn notify(&mut self, event: PoolEvent);
+}
+
+/// Selector of `StatsSubscriber::notify`, as ink! computes it for the trait
+pub const NOTIFY_SELECTOR: [u8; 4] = <<ink_lang::reflect::TraitDefinitionRegistry<
+    Environment,
+> as StatsSubscriber>::__ink_TraitInfo as ink_lang::reflect::TraitMessageInfo<
+    { ink_lang::selector_id!("notify") },
+>>::SELECTOR;
+
+/// Gas a `notify` call gets at most, i.e. 5 ms of execution
+pub const SUBSCRIBER_GAS_LIMIT: u64 = 5_000_000_000;
+
+/// Call `notify` on the subscriber, returns the error of the call
+#[cfg(not(test))]
+fn send_notify(subscriber: AccountId, event: &PoolEvent) -> Result<(), ink_env::Error> {
+    use ink_env::call::{build_call, Call, ExecutionInput, Selector};
+
+    build_call::<Environment>()
+        .call_type(
+            Call::new()
+                .callee(subscriber)
+                .gas_limit(SUBSCRIBER_GAS_LIMIT),
+        )
+        .exec_input(ExecutionInput::new(Selector::new(NOTIFY_SELECTOR)).push_arg(event))
+        .returns::<()>()
+        .fire()
+}
+
+/// Record the `notify` call for the unit tests instead, the off-chain
+/// environment can't call contracts
+#[cfg(test)]
+fn send_notify(subscriber: AccountId, event: &PoolEvent) -> Result<(), ink_env::Error> {
+    tests::NOTIFIED.with(|notified| notified.borrow_mut().push((subscriber, event.clone())));
+
+    match tests::SUBSCRIBER_TRAPS.with(|traps| traps.get()) {
+        true => Err(ink_env::Error::CalleeTrapped),
+        false => Ok(()),
+    }
+}
+
+impl Slushie {
+    /// Notify the stats subscriber, if there is one, about the operation
+    /// after its state changes
+    pub(crate) fn notify_subscriber(&self, kind: PoolEventKind) {
+        if let Some(subscriber) = self.stats_subscriber {
+            let event = PoolEvent {
+                pool: self.env().account_id(),
+                kind,
+                timestamp: self.env().block_timestamp(),
+                deposit_count: self.merkle_tree.next_index,
+                withdrawn_count: self.withdrawn_count,
+            };
+
+            // the subscriber must never block the pool, its errors are dropped
+            let _ = send_notify(subscriber, &event);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::*;
+    use std::cell::{Cell, RefCell};
+
+    /// Imports `ink_lang` so we can use `#[ink::test]`.
+    use ink_lang as ink;
+
+    thread_local! {
+        /// `notify` calls sent by the pool
+        pub(super) static NOTIFIED: RefCell<Vec<(AccountId, PoolEvent)>> = const { RefCell::new(Vec::new()) };
+        /// Whether the subscriber traps on `notify`
+        pub(super) static SUBSCRIBER_TRAPS: Cell<bool> = const { Cell::new(false) };
+    }
+
+    fn notified() -> Vec<(AccountId, PoolEvent)> {
+        NOTIFIED.with(|notified| notified.take())
+    }
+
+    /// - `NOTIFY_SELECTOR` is the default selector of the trait message,
+    ///   subscribers implementing the trait are called with it
+    #[test]
+    fn notify_selector_is_pinned() {
+        use ink_env::hash::{Blake2x256, CryptoHash};
+
+        let mut hash = [0; 32];
+        Blake2x256::hash(b"StatsSubscriber::notify", &mut hash);
+
+        assert_eq!(NOTIFY_SELECTOR, hash[..4]);
+    }
+
+    /// - only the owner can set or clear the subscriber
+    #[ink::test]
+    fn set_stats_subscriber_works() {
+        let accounts = ink_env::test::default_accounts::<ink_env::DefaultEnvironment>();
+        let mut slushie: Slushie = Slushie::new(13);
+        assert_eq!(slushie.get_stats_subscriber(), None);
+
+        ink_env::test::set_caller::<Environment>(accounts.bob);
+        assert_eq!(
+            slushie.set_stats_subscriber(Some(accounts.bob)),
+            Err(Error::CallerIsNotOwner)
+        );
+
+        ink_env::test::set_caller::<Environment>(accounts.alice);
+        assert_eq!(slushie.set_stats_subscriber(Some(accounts.eve)), Ok(()));
+        assert_eq!(slushie.get_stats_subscriber(), Some(accounts.eve));
+
+        assert_eq!(slushie.set_stats_subscriber(None), Ok(()));
+        assert_eq!(slushie.get_stats_subscriber(), None);
+    }
+
+    /// - nothing is sent without a subscriber
+    #[ink::test]
+    fn no_subscriber_is_not_notified() {
+        let mut slushie: Slushie = Slushie::new(13);
+        let mut model = Model::default();
+
+        let root = deposit_checked(&mut slushie, &mut model, [1; 32]);
+        withdraw_checked(&mut slushie, &mut model, [2; 32], root);
+
+        assert_eq!(notified(), []);
+    }
+
+    /// - the subscriber gets the aggregate data of every successful deposit and withdrawal
+    #[ink::test]
+    fn subscriber_is_notified() {
+        let accounts = ink_env::test::default_accounts::<ink_env::DefaultEnvironment>();
+        let pool = ink_env::test::callee::<ink_env::DefaultEnvironment>();
+        let mut slushie: Slushie = Slushie::new(13);
+        let mut model = Model::default();
+        slushie.set_stats_subscriber(Some(accounts.eve)).unwrap();
+
+        let root = deposit_checked(&mut slushie, &mut model, [1; 32]);
+        ink_env::test::set_caller::<Environment>(accounts.bob);
+        slushie
+            .deposit_for([3; 32], accounts.charlie, None)
+            .unwrap_err();
+        withdraw_checked(&mut slushie, &mut model, [2; 32], root);
+
+        let timestamp = ink_env::block_timestamp::<Environment>();
+        assert_eq!(
+            notified(),
+            [
+                (
+                    accounts.eve,
+                    PoolEvent {
+                        pool,
+                        kind: PoolEventKind::Deposit,
+                        timestamp,
+                        deposit_count: 1,
+                        withdrawn_count: 0,
+                    }
+                ),
+                (
+                    accounts.eve,
+                    PoolEvent {
+                        pool,
+                        kind: PoolEventKind::Withdrawal,
+                        timestamp,
+                        deposit_count: 1,
+                        withdrawn_count: 1,
+                    }
+                ),
+            ]
+        );
+    }
+
+    /// - a trapping subscriber doesn't block deposits and withdrawals
+    #[ink::test]
+    fn subscriber_errors_are_swallowed() {
+        let accounts = ink_env::test::default_accounts::<ink_env::DefaultEnvironment>();
+        let mut slushie: Slushie = Slushie::new(13);
+        let mut model = Model::default();
+        slushie.set_stats_subscriber(Some(accounts.eve)).unwrap();
+        SUBSCRIBER_TRAPS.with(|traps| traps.set(true));
+
+        let root = deposit_checked(&mut slushie, &mut model, [1; 32]);
+        withdraw_checked(&mut slushie, &mut model, [2; 32], root);
+
+        assert_eq!(notified().len(), 2);
+    }
+}
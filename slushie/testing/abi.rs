@@ -0,0 +1,337 @@
+//! SCALE round-trips of the ABI types, i.e. of everything the messages take
+//! or return and of the events
+
+use super::*;
+
+/// Number of random instances generated for each SCALE round-trip test
+const ROUNDTRIP_CASES: usize = 256;
+
+fn random_option<T>(rng: &mut impl rand::Rng, value: T) -> Option<T> {
+    rng.gen::<bool>().then_some(value)
+}
+
+fn random_string(rng: &mut impl rand::Rng) -> String {
+    let len = rng.gen_range(0..48);
+    (0..len).map(|_| rng.gen_range('!'..='~')).collect()
+}
+
+fn random_build_info(rng: &mut impl rand::Rng) -> BuildInfo {
+    let git_commit = random_string(rng);
+    BuildInfo {
+        crate_version: random_string(rng),
+        git_commit: random_option(rng, git_commit),
+        features: (0..rng.gen_range(0..8))
+            .map(|_| random_string(rng))
+            .collect(),
+        hasher: random_option(rng, HasherKind::Blake).unwrap_or(HasherKind::Poseidon),
+        max_depth: rng.gen(),
+        protocol_version: rng.gen(),
+    }
+}
+
+/// Assert that `value` decodes back from its SCALE encoding
+fn assert_roundtrip<T>(value: T)
+where
+    T: scale::Encode + scale::Decode + PartialEq + core::fmt::Debug,
+{
+    let encoded = value.encode();
+    let decoded = T::decode(&mut &encoded[..]).expect("encoded value must decode");
+    assert_eq!(decoded, value);
+}
+
+/// Decoding arbitrary bytes never panics, and whatever decodes
+/// encodes back to the consumed input
+fn assert_decodes_arbitrary_bytes<T>(rng: &mut impl rand::Rng)
+where
+    T: scale::Encode + scale::Decode + PartialEq + core::fmt::Debug,
+{
+    for _ in 0..ROUNDTRIP_CASES {
+        let len = rng.gen_range(0..160);
+        let bytes: Vec<u8> = (0..len).map(|_| rng.gen()).collect();
+
+        let mut input = &bytes[..];
+        if let Ok(decoded) = T::decode(&mut input) {
+            let consumed = bytes.len() - input.len();
+            assert_eq!(decoded.encode(), bytes[..consumed]);
+        }
+    }
+}
+
+/// - every `Error` variant survives a SCALE round-trip
+#[test]
+fn error_scale_roundtrip() {
+    let variants: Vec<Error> = (0..=u8::MAX)
+        .filter_map(|index| Error::decode(&mut &[index][..]).ok())
+        .collect();
+
+    assert_eq!(variants.first(), Some(&Error::DepositFailure));
+    assert_eq!(variants.last(), Some(&Error::NoAccountingViolation));
+
+    variants.into_iter().for_each(assert_roundtrip);
+}
+
+/// - random instances of every event survive a SCALE round-trip
+#[test]
+fn events_scale_roundtrip() {
+    let mut rng = rng();
+
+    for _ in 0..ROUNDTRIP_CASES {
+        let (version, hash, timestamp) = (rng.gen(), random_hash(&mut rng), rng.gen());
+        let deposited = Deposited {
+            version,
+            commitment: hash,
+            timestamp,
+        };
+        let decoded = Deposited::decode(&mut &deposited.encode()[..]).unwrap();
+        assert_eq!(
+            (decoded.version, decoded.commitment, decoded.timestamp),
+            (version, hash, timestamp)
+        );
+
+        let withdrawn = Withdrawn {
+            version,
+            nullifier_hash: hash,
+            timestamp,
+        };
+        let decoded = Withdrawn::decode(&mut &withdrawn.encode()[..]).unwrap();
+        assert_eq!(
+            (decoded.version, decoded.nullifier_hash, decoded.timestamp),
+            (version, hash, timestamp)
+        );
+
+        let (beneficiary, memo) = (AccountId::from(random_hash(&mut rng)), rng.gen());
+        let memo = random_option(&mut rng, memo);
+        let deposited_for = DepositedFor {
+            version,
+            commitment: hash,
+            beneficiary,
+            memo,
+        };
+        let decoded = DepositedFor::decode(&mut &deposited_for.encode()[..]).unwrap();
+        assert_eq!(
+            (
+                decoded.version,
+                decoded.commitment,
+                decoded.beneficiary,
+                decoded.memo
+            ),
+            (version, hash, beneficiary, memo)
+        );
+
+        let (deposit_size, depth, root_history_size) = (rng.gen(), rng.gen(), rng.gen());
+        let build_info = random_build_info(&mut rng);
+        let pool_initialized = PoolInitialized {
+            version,
+            deposit_size,
+            depth,
+            root_history_size,
+            zeros_top: hash,
+            build_info: build_info.clone(),
+        };
+        let decoded = PoolInitialized::decode(&mut &pool_initialized.encode()[..]).unwrap();
+        assert_eq!(
+            (
+                decoded.version,
+                decoded.deposit_size,
+                decoded.depth,
+                decoded.root_history_size,
+                decoded.zeros_top,
+                decoded.build_info
+            ),
+            (
+                version,
+                deposit_size,
+                depth,
+                root_history_size,
+                hash,
+                build_info
+            )
+        );
+
+        let (withdrawn_count, deposit_count) = (rng.gen(), rng.gen());
+        let emergency_paused = EmergencyPaused {
+            version,
+            withdrawn_count,
+            deposit_count,
+        };
+        let decoded = EmergencyPaused::decode(&mut &emergency_paused.encode()[..]).unwrap();
+        assert_eq!(
+            (
+                decoded.version,
+                decoded.withdrawn_count,
+                decoded.deposit_count
+            ),
+            (version, withdrawn_count, deposit_count)
+        );
+
+        let (recipient, amount) = (AccountId::from(random_hash(&mut rng)), rng.gen());
+        let payout_claimed = PayoutClaimed {
+            version,
+            nullifier_hash: hash,
+            recipient,
+            amount,
+        };
+        let decoded = PayoutClaimed::decode(&mut &payout_claimed.encode()[..]).unwrap();
+        assert_eq!(
+            (
+                decoded.version,
+                decoded.nullifier_hash,
+                decoded.recipient,
+                decoded.amount
+            ),
+            (version, hash, recipient, amount)
+        );
+
+        let (outflow, principal) = (rng.gen(), rng.gen());
+        let tripped = CircuitBreakerTripped {
+            version,
+            outflow,
+            principal,
+        };
+        let decoded = CircuitBreakerTripped::decode(&mut &tripped.encode()[..]).unwrap();
+        assert_eq!(
+            (decoded.version, decoded.outflow, decoded.principal),
+            (version, outflow, principal)
+        );
+    }
+}
+
+/// - random instances of every message argument and return type survive
+///   a SCALE round-trip
+#[test]
+fn message_types_scale_roundtrip() {
+    let mut rng = rng();
+
+    for _ in 0..ROUNDTRIP_CASES {
+        assert_roundtrip(WithdrawalReceipt {
+            nullifier_hash: random_hash(&mut rng),
+            recipient: AccountId::from(random_hash(&mut rng)),
+            amount: rng.gen(),
+            root: random_hash(&mut rng),
+            timestamp: rng.gen(),
+        });
+
+        let siblings = (0..rng.gen_range(0..=MAX_DEPTH))
+            .map(|_| random_hash(&mut rng))
+            .collect();
+        assert_roundtrip(MerkleProof::<PoseidonHash> {
+            leaf_index: rng.gen(),
+            siblings,
+        });
+
+        let deposit_timestamp = rng.gen();
+        let root_at_insertion = random_hash(&mut rng);
+        assert_roundtrip(RootStatus::Historical { age: rng.gen() });
+
+        assert_roundtrip(CommitmentInfo {
+            leaf_index: rng.gen(),
+            deposit_timestamp: random_option(&mut rng, deposit_timestamp),
+            root_at_insertion: random_option(&mut rng, root_at_insertion),
+        });
+
+        assert_roundtrip(PoolStats {
+            deposit_count: rng.gen(),
+            withdrawn_count: rng.gen(),
+            withdrawals_paused: rng.gen(),
+        });
+
+        assert_roundtrip(Payout {
+            recipient: AccountId::from(random_hash(&mut rng)),
+            amount: rng.gen(),
+        });
+
+        assert_roundtrip(CircuitBreakerStatus {
+            max_outflow_percent: rng.gen(),
+            window_blocks: rng.gen(),
+            tripped: rng.gen(),
+            outflow: rng.gen(),
+        });
+
+        assert_roundtrip(random_build_info(&mut rng));
+
+        assert_roundtrip(PoolOptions {
+            store_insertion_roots: rng.gen(),
+            track_deposit_times: rng.gen(),
+            pull_payments: rng.gen(),
+            max_outflow_percent: rng.gen(),
+            outflow_window_blocks: rng.gen(),
+        });
+
+        let kind = match rng.gen() {
+            true => PoolEventKind::Deposit,
+            false => PoolEventKind::Withdrawal,
+        };
+        assert_roundtrip(PoolEvent {
+            pool: AccountId::from(random_hash(&mut rng)),
+            kind,
+            timestamp: rng.gen(),
+            deposit_count: rng.gen(),
+            withdrawn_count: rng.gen(),
+        });
+    }
+
+    assert_roundtrip(RootStatus::Current);
+    assert_roundtrip(RootStatus::Unknown);
+    assert_roundtrip(HasherKind::Poseidon);
+    assert_roundtrip(HasherKind::Blake);
+}
+
+/// - decoding arbitrary bytes into any of the ABI types never panics
+#[test]
+fn decode_arbitrary_bytes_never_panics() {
+    let mut rng = rng();
+
+    assert_decodes_arbitrary_bytes::<Error>(&mut rng);
+    assert_decodes_arbitrary_bytes::<WithdrawalReceipt>(&mut rng);
+    assert_decodes_arbitrary_bytes::<MerkleProof<PoseidonHash>>(&mut rng);
+    assert_decodes_arbitrary_bytes::<CommitmentInfo>(&mut rng);
+    assert_decodes_arbitrary_bytes::<RootStatus>(&mut rng);
+    assert_decodes_arbitrary_bytes::<BuildInfo>(&mut rng);
+    assert_decodes_arbitrary_bytes::<Payout>(&mut rng);
+    assert_decodes_arbitrary_bytes::<CircuitBreakerStatus>(&mut rng);
+    assert_decodes_arbitrary_bytes::<PoolStats>(&mut rng);
+    assert_decodes_arbitrary_bytes::<HasherKind>(&mut rng);
+    assert_decodes_arbitrary_bytes::<PoolOptions>(&mut rng);
+    assert_decodes_arbitrary_bytes::<PoolEvent>(&mut rng);
+}
+
+extern "Rust" {
+    /// Metadata of the contract, generated by `#[ink::contract]` for `cargo contract`
+    fn __ink_generate_metadata() -> ink_metadata::MetadataVersioned;
+}
+
+/// Labels of the messages in the contract metadata, with whether they are payable
+fn metadata_messages() -> Vec<(String, bool)> {
+    // the symbol is defined by the contract, without arguments
+    match unsafe { __ink_generate_metadata() } {
+        ink_metadata::MetadataVersioned::V3(project) => project
+            .spec()
+            .messages()
+            .iter()
+            .map(|message| (message.label().clone(), message.payable()))
+            .collect(),
+        _ => panic!("unexpected metadata version"),
+    }
+}
+
+/// - the messages refusing value with `UnexpectedTransferredValue` are payable,
+///   a non-payable message would trap in the dispatch before the check runs
+#[test]
+fn messages_refusing_value_are_payable() {
+    let payable: Vec<String> = metadata_messages()
+        .into_iter()
+        .filter(|(_, payable)| *payable)
+        .map(|(label, _)| label)
+        .collect();
+
+    assert_eq!(
+        payable,
+        [
+            "deposit",
+            "deposit_for",
+            "withdraw",
+            "claim_payout",
+            "check_withdraw"
+        ]
+    );
+}
@@ -0,0 +1,231 @@
+//! Allowed state transitions of the pool, checked after operations
+//!
+//! `Model` records what a test did to the contract and what it last
+//! observed. `assert_invariants` checks that:
+//! - the tree only grows, by one leaf per deposited commitment
+//! - spent nullifiers stay spent, one per successful withdrawal
+//! - there are never more withdrawals than deposits
+//! - pending payouts add up to the unclaimed recorded payouts
+//! - paused withdrawals stay paused
+
+use super::*;
+
+/// Imports `ink_lang` so we can use `#[ink::test]`.
+use ink_lang as ink;
+
+#[derive(Default)]
+pub(crate) struct Model {
+    /// Deposited commitments, in the order of their leaves
+    pub deposits: Vec<PoseidonHash>,
+    /// Nullifier hashes of the successful withdrawals
+    pub withdrawals: Vec<PoseidonHash>,
+    /// Nullifier hashes of the unclaimed pull payouts
+    pub unclaimed: Vec<PoseidonHash>,
+    /// Whether withdrawals are expected to be paused
+    pub paused: bool,
+    /// Stats seen by the previous check, kept by `assert_invariants`
+    pub observed: Option<PoolStats>,
+}
+
+pub(crate) fn assert_invariants(contract: &Slushie, model: &mut Model) {
+    let stats = contract.get_stats();
+
+    assert!(
+        stats.withdrawn_count <= stats.deposit_count,
+        "more withdrawals than deposits"
+    );
+
+    assert_eq!(
+        stats.deposit_count, contract.merkle_tree.next_index,
+        "deposit count differs from the tree"
+    );
+    assert_eq!(
+        stats.deposit_count,
+        model.deposits.len() as u64,
+        "deposit count differs from the model"
+    );
+    for (index, commitment) in model.deposits.iter().enumerate() {
+        assert_eq!(
+            contract.get_commitment_index(*commitment),
+            Some(index as u32),
+            "commitment isn't at its leaf"
+        );
+    }
+
+    assert_eq!(
+        stats.withdrawn_count,
+        model.withdrawals.len() as u64,
+        "withdrawal count differs from the model"
+    );
+    for nullifier_hash in &model.withdrawals {
+        assert!(
+            contract.used_nullifiers.get(nullifier_hash).is_some(),
+            "nullifier of a withdrawal isn't spent"
+        );
+    }
+
+    assert_eq!(
+        contract.pending_payouts,
+        model.unclaimed.len() as Balance * contract.deposit_size,
+        "pending payouts differ from the unclaimed payouts"
+    );
+    for nullifier_hash in &model.unclaimed {
+        assert!(
+            contract.get_payout(*nullifier_hash).is_some(),
+            "unclaimed payout is missing"
+        );
+    }
+
+    assert_eq!(
+        stats.withdrawals_paused, model.paused,
+        "pause differs from the model"
+    );
+
+    if let Some(observed) = &model.observed {
+        assert!(
+            stats.deposit_count >= observed.deposit_count,
+            "deposit count went back"
+        );
+        assert!(
+            stats.withdrawn_count >= observed.withdrawn_count,
+            "withdrawal count went back"
+        );
+        assert!(
+            stats.withdrawals_paused || !observed.withdrawals_paused,
+            "withdrawals were unpaused"
+        );
+    }
+    model.observed = Some(stats);
+}
+
+/// Deposit the commitment, record it and check the invariants
+pub(crate) fn deposit_checked(
+    contract: &mut Slushie,
+    model: &mut Model,
+    commitment: PoseidonHash,
+) -> PoseidonHash {
+    ink_env::test::set_value_transferred::<ink_env::DefaultEnvironment>(contract.deposit_size);
+    let root = contract.deposit(commitment).unwrap();
+    ink_env::test::set_value_transferred::<ink_env::DefaultEnvironment>(0);
+
+    model.deposits.push(commitment);
+    assert_invariants(contract, model);
+
+    root
+}
+
+/// Withdraw with the nullifier hash, record it and check the invariants
+pub(crate) fn withdraw_checked(
+    contract: &mut Slushie,
+    model: &mut Model,
+    nullifier_hash: PoseidonHash,
+    root: PoseidonHash,
+) -> WithdrawalReceipt {
+    let receipt = contract.withdraw(nullifier_hash, root).unwrap();
+
+    model.withdrawals.push(nullifier_hash);
+    if contract.pull_payments {
+        model.unclaimed.push(nullifier_hash);
+    }
+    assert_invariants(contract, model);
+
+    receipt
+}
+
+/// - random sequences of deposits, withdrawals, replays and claims keep
+///   the invariants in both payment modes
+#[ink::test]
+fn random_operations_keep_invariants() {
+    let deposit_size = 13;
+    let mut rng = rng();
+
+    for pull_payments in [false, true] {
+        let mut slushie: Slushie = Slushie::new_with_options(
+            deposit_size,
+            PoolOptions {
+                pull_payments,
+                ..Default::default()
+            },
+        );
+        let mut model = Model::default();
+        let mut root = slushie.get_root_hash();
+
+        for _ in 0..24 {
+            match rng.gen_range(0..4) {
+                0 => {
+                    let commitment = random_hash(&mut rng);
+                    ink_env::test::set_value_transferred::<ink_env::DefaultEnvironment>(
+                        deposit_size,
+                    );
+                    root = slushie.deposit(commitment).unwrap();
+                    model.deposits.push(commitment);
+                }
+                1 if model.deposits.len() > model.withdrawals.len() => {
+                    let nullifier_hash = random_hash(&mut rng);
+                    ink_env::test::set_value_transferred::<ink_env::DefaultEnvironment>(0);
+                    slushie.withdraw(nullifier_hash, root).unwrap();
+                    model.withdrawals.push(nullifier_hash);
+                    if pull_payments {
+                        model.unclaimed.push(nullifier_hash);
+                    }
+                }
+                2 if !model.withdrawals.is_empty() => {
+                    let spent = model.withdrawals[rng.gen_range(0..model.withdrawals.len())];
+                    ink_env::test::set_value_transferred::<ink_env::DefaultEnvironment>(0);
+                    assert_eq!(
+                        slushie.withdraw(spent, root),
+                        Err(Error::NullifierAlreadyUsed)
+                    );
+                }
+                3 if !model.unclaimed.is_empty() => {
+                    let nullifier_hash = model
+                        .unclaimed
+                        .remove(rng.gen_range(0..model.unclaimed.len()));
+                    ink_env::test::set_value_transferred::<ink_env::DefaultEnvironment>(0);
+                    slushie.claim_payout(nullifier_hash).unwrap();
+                }
+                _ => {
+                    let duplicate = model.deposits.first().copied();
+                    if let Some(commitment) = duplicate {
+                        ink_env::test::set_value_transferred::<ink_env::DefaultEnvironment>(
+                            deposit_size,
+                        );
+                        assert_eq!(slushie.deposit(commitment), Err(Error::DuplicateCommitment));
+                    }
+                }
+            }
+
+            assert_invariants(&slushie, &mut model);
+        }
+    }
+}
+
+/// - the invariants catch a withdrawal counter which went back
+#[ink::test]
+#[should_panic(expected = "withdrawal count went back")]
+fn invariants_catch_a_broken_withdrawal_counter() {
+    let deposit_size = 13;
+    let mut slushie: Slushie = Slushie::new(deposit_size);
+    let mut model = Model::default();
+
+    let root = deposit_checked(&mut slushie, &mut model, [1; 32]);
+    withdraw_checked(&mut slushie, &mut model, [2; 32], root);
+
+    slushie.__set_withdrawn_count(0);
+    model.withdrawals.clear();
+    assert_invariants(&slushie, &mut model);
+}
+
+/// - the invariants catch more withdrawals than deposits
+#[ink::test]
+#[should_panic(expected = "more withdrawals than deposits")]
+fn invariants_catch_withdrawals_outnumbering_deposits() {
+    let deposit_size = 13;
+    let mut slushie: Slushie = Slushie::new(deposit_size);
+    let mut model = Model::default();
+
+    deposit_checked(&mut slushie, &mut model, [1; 32]);
+
+    slushie.__set_withdrawn_count(2);
+    assert_invariants(&slushie, &mut model);
+}
@@ -0,0 +1,102 @@
+//! Shared setup of the unit tests
+//!
+//! Every test module imports this prelude, together with the event and the
+//! randomness helpers.
+
+mod abi;
+mod invariants;
+mod sources;
+
+pub(crate) use crate::errors::Error;
+pub(crate) use crate::protocol_constants::{
+    CIRCUIT_BREAKER_TRIPPED_EVENT, DEPOSITED_EVENT, DEPOSITED_FOR_EVENT, EMERGENCY_PAUSED_EVENT,
+    PAYOUT_CLAIMED_EVENT, POOL_INITIALIZED_EVENT, WITHDRAWN_EVENT,
+};
+pub(crate) use crate::slushie::*;
+pub(crate) use crate::subscriber::{PoolEvent, PoolEventKind};
+pub(crate) use crate::tree::hasher::MerkleTreeHasher;
+pub(crate) use crate::tree::merkle_tree::{
+    MerkleProof, MerkleTree, DEFAULT_ROOT_HISTORY_SIZE, MAX_DEPTH,
+};
+pub(crate) use crate::{AccountId, Balance, BlockNumber, Environment};
+pub(crate) use hex_literal::hex;
+pub(crate) use invariants::{assert_invariants, deposit_checked, withdraw_checked, Model};
+pub(crate) use rand::Rng;
+pub(crate) use scale::{Decode, Encode};
+
+/// Topic as it is computed by the ink! topics builder
+pub(crate) fn encoded_into_hash<T: scale::Encode>(entity: &T) -> Vec<u8> {
+    encoded_topic(entity.encode())
+}
+
+/// Topic of an already encoded value
+pub(crate) fn encoded_topic(encoded: Vec<u8>) -> Vec<u8> {
+    use ink_env::hash::{Blake2x256, CryptoHash};
+
+    if encoded.len() <= 32 {
+        let mut result = vec![0; 32];
+        result[..encoded.len()].copy_from_slice(&encoded);
+        return result;
+    }
+
+    let mut result = [0; 32];
+    Blake2x256::hash(&encoded, &mut result);
+    result.to_vec()
+}
+
+/// Topics of the last emitted event
+pub(crate) fn last_event_topics() -> Vec<Vec<u8>> {
+    ink_env::test::recorded_events().last().unwrap().topics
+}
+
+/// Events emitted after the constructor's `PoolInitialized`
+pub(crate) fn events_after_instantiation() -> Vec<ink_env::test::EmittedEvent> {
+    ink_env::test::recorded_events().skip(1).collect()
+}
+
+pub(crate) fn event_topic(prefix: &[u8], value: &PoseidonHash) -> Vec<u8> {
+    encoded_into_hash(&ink_env::topics::PrefixedValue { prefix, value })
+}
+
+/// Signature topic, encoded as `PrefixedValue { prefix: b"", value: &[u8; N] }`
+pub(crate) fn signature_topic(signature: &str) -> Vec<u8> {
+    let mut encoded = b"".as_slice().encode();
+    encoded.extend_from_slice(signature.as_bytes());
+
+    encoded_topic(encoded)
+}
+
+pub(crate) fn rng() -> rand::rngs::StdRng {
+    use rand::SeedableRng;
+    rand::rngs::StdRng::seed_from_u64(0x51u64)
+}
+
+pub(crate) fn random_hash(rng: &mut impl rand::Rng) -> PoseidonHash {
+    rng.gen()
+}
+
+/// Pool with the circuit breaker and `deposits` deposits of 13, returns
+/// the pool, its root and the model of its invariants
+pub(crate) fn pool_with_circuit_breaker(
+    max_outflow_percent: u8,
+    window_blocks: BlockNumber,
+    deposits: u8,
+) -> (Slushie, PoseidonHash, Model) {
+    let deposit_size = 13;
+    let mut slushie: Slushie = Slushie::new_with_options(
+        deposit_size,
+        PoolOptions {
+            max_outflow_percent,
+            outflow_window_blocks: window_blocks,
+            ..Default::default()
+        },
+    );
+
+    let mut model = Model::default();
+    for commitment in 1..=deposits {
+        deposit_checked(&mut slushie, &mut model, [commitment; 32]);
+    }
+
+    let root = slushie.get_root_hash();
+    (slushie, root, model)
+}
@@ -0,0 +1,286 @@
+//! Scans of the contract sources and of its dependency graph
+
+/// - the contract's dependencies build without std: in the graph cargo
+///   resolves for a Wasm build of either hasher no crate, transitive ones
+///   included, enables its `std` feature or is a std-only crate. Proc
+///   macros and build scripts run on the host and are left out.
+#[test]
+fn contract_dependencies_are_no_std() {
+    let cargo = std::env::var("CARGO").unwrap_or_else(|_| "cargo".into());
+    let manifest = concat!(env!("CARGO_MANIFEST_DIR"), "/Cargo.toml");
+    let std_only = [
+        "subxt",
+        "serde_json",
+        "reqwest",
+        "tokio",
+        "jsonrpsee",
+        "rayon",
+    ];
+
+    for hasher in ["contract-hasher-poseidon", "contract-hasher-blake"] {
+        let output = std::process::Command::new(&cargo)
+            .args(["tree", "--offline", "--manifest-path", manifest])
+            .args([
+                "-p",
+                "slushie",
+                "--no-default-features",
+                "--features",
+                hasher,
+            ])
+            .args(["-e", "normal,features,no-proc-macro", "--prefix", "none"])
+            .args(["--target", "wasm32-unknown-unknown"])
+            .output()
+            .expect("cargo tree must run");
+        assert!(
+            output.status.success(),
+            "cargo tree failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+
+        let tree = String::from_utf8(output.stdout).unwrap();
+        for line in tree.lines() {
+            let name = line.split(' ').next().unwrap_or_default();
+            assert!(
+                !line.contains("feature \"std\""),
+                "{}: {} enables std",
+                hasher,
+                name
+            );
+            assert!(
+                !std_only.contains(&name),
+                "{}: {} is std-only",
+                hasher,
+                name
+            );
+        }
+    }
+}
+
+/// Lines of the non-test code with their 1-based numbers, comments skipped
+fn code_lines(source: &str) -> Vec<(usize, &str)> {
+    let lines: Vec<&str> = source.lines().collect();
+    // everything from the first inline test module on is test code
+    let code_end = lines
+        .windows(2)
+        .position(|pair| {
+            pair[0].trim() == "#[cfg(test)]"
+                && pair[1].trim().starts_with("mod ")
+                && pair[1].trim().ends_with('{')
+        })
+        .unwrap_or(lines.len());
+
+    lines[..code_end]
+        .iter()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().starts_with("//"))
+        .map(|(number, line)| (number + 1, *line))
+        .collect()
+}
+
+/// Code lines which go into a release build: `code_lines` without the
+/// test-only items and the items gated on the `dev-assertions` feature
+fn release_code_lines(source: &str) -> Vec<(usize, &str)> {
+    let gates = [
+        "#[cfg(test)]",
+        "#[cfg(any(test, feature = \"dev-assertions\"))]",
+    ];
+    let mut lines = code_lines(source).into_iter();
+    let mut release = Vec::new();
+
+    while let Some((number, line)) = lines.next() {
+        if !gates.contains(&line.trim()) {
+            release.push((number, line));
+            continue;
+        }
+        // skip the gated statement or item, up to its closing brace
+        let mut depth: i32 = 0;
+        for (_, gated) in lines.by_ref() {
+            depth += gated.matches('{').count() as i32;
+            depth -= gated.matches('}').count() as i32;
+            let trimmed = gated.trim_end();
+            if depth == 0 && (trimmed.ends_with(';') || trimmed.ends_with('}')) {
+                break;
+            }
+        }
+    }
+    release
+}
+
+/// Whether the line indexes into an array or a slice, full ranges aside
+fn is_indexing(line: &str) -> bool {
+    line.match_indices('[').any(|(at, _)| {
+        let indexed = line[..at]
+            .chars()
+            .last()
+            .is_some_and(|c| c.is_alphanumeric() || "_)]".contains(c));
+        indexed && !line[at..].starts_with("[..]")
+    })
+}
+
+/// The contract and the tree code must not panic, errors are returned instead
+///
+/// Scans the release code for unwraps, panicking macros and indexing.
+/// Arithmetic isn't scanned, it goes through `CheckedMath`. The few
+/// sites which can't panic, or have to, are allowlisted with the
+/// reason, a stale entry fails the test as well.
+#[test]
+fn no_panic_paths_in_contract_sources() {
+    let sources = [
+        ("lib.rs", include_str!("../lib.rs")),
+        ("errors.rs", include_str!("../errors.rs")),
+        ("storage.rs", include_str!("../storage.rs")),
+        ("messages/mod.rs", include_str!("../messages/mod.rs")),
+        (
+            "messages/deposit.rs",
+            include_str!("../messages/deposit.rs"),
+        ),
+        (
+            "messages/withdraw.rs",
+            include_str!("../messages/withdraw.rs"),
+        ),
+        ("messages/admin.rs", include_str!("../messages/admin.rs")),
+        ("subscriber.rs", include_str!("../subscriber.rs")),
+        ("math.rs", include_str!("../math.rs")),
+        (
+            "protocol_constants.rs",
+            include_str!("../protocol_constants.rs"),
+        ),
+        ("tree/mod.rs", include_str!("../tree/mod.rs")),
+        ("tree/hasher.rs", include_str!("../tree/hasher.rs")),
+        ("tree/light.rs", include_str!("../tree/light.rs")),
+        (
+            "tree/merkle_tree.rs",
+            include_str!("../tree/merkle_tree.rs"),
+        ),
+    ];
+    let forbidden = [
+        ".unwrap(",
+        ".expect(",
+        "panic!(",
+        "assert!(",
+        "assert_eq!(",
+        "assert_ne!(",
+        "unreachable!(",
+        "unimplemented!(",
+        "todo!(",
+        "indexing",
+    ];
+    // (file, token, fragment of the line and the next one)
+    let allowed = [
+        // constructors can't return a Result, they abort the instantiation
+        (
+            "messages/admin.rs",
+            "assert!(",
+            "assert!( top == *table_top,",
+        ),
+        (
+            "lib.rs",
+            "assert!(",
+            "assert!( Self::is_valid_circuit_breaker(",
+        ),
+        // const index into the ZEROS ladder, checked by the compiler
+        (
+            "messages/withdraw.rs",
+            "indexing",
+            "TreeHasher::ZEROS[MAX_DEPTH - 1];",
+        ),
+        // evaluated at compile time
+        (
+            "tree/merkle_tree.rs",
+            "assert!(",
+            "const VALID_DEPTH: () = assert!(",
+        ),
+        // DEPTH is in 1..=MAX_DEPTH, the length of ZEROS
+        ("tree/merkle_tree.rs", "indexing", "Hash::ZEROS[DEPTH - 1]"),
+        ("tree/merkle_tree.rs", "indexing", "&Hash::ZEROS[0..DEPTH]"),
+        // current_root_index is taken modulo ROOT_HISTORY_SIZE
+        (
+            "tree/merkle_tree.rs",
+            "indexing",
+            "self.roots.0[self.current_root_index as usize]",
+        ),
+        // padded is at least one byte longer than data
+        ("tree/hasher.rs", "indexing", "padded[..data.len()]"),
+        ("tree/hasher.rs", "indexing", "padded[data.len()]"),
+        // a chunk of 31 bytes into 32
+        ("tree/hasher.rs", "indexing", "bytes[..POSEIDON_CHUNK_SIZE]"),
+    ];
+    let mut used = vec![false; allowed.len()];
+
+    for (file, source) in sources {
+        let lines = release_code_lines(source);
+        for (position, (number, line)) in lines.iter().enumerate() {
+            let next = lines.get(position + 1).map_or("", |(_, next)| next.trim());
+            let context = format!("{} {}", line.trim(), next);
+
+            for token in forbidden {
+                let found = match token {
+                    "indexing" => is_indexing(line),
+                    _ => line.contains(token),
+                };
+                if !found {
+                    continue;
+                }
+
+                let entry = allowed
+                    .iter()
+                    .position(|(allowed_file, allowed_token, fragment)| {
+                        *allowed_file == file
+                            && *allowed_token == token
+                            && context.contains(fragment)
+                    });
+                match entry {
+                    Some(entry) => used[entry] = true,
+                    None => {
+                        panic!("{}:{} contains `{}`: {}", file, number, token, line.trim())
+                    }
+                }
+            }
+        }
+    }
+
+    for (entry, used) in allowed.iter().zip(used) {
+        assert!(used, "allowlisted {:?} no longer occurs", entry);
+    }
+}
+
+/// The tree builds without std, so it only allocates through `ink_prelude`.
+/// `scripts/check-no-std.sh` builds it for Wasm, this only catches the usual
+/// regressions early in `cargo test`.
+#[test]
+fn tree_sources_are_no_std() {
+    let sources = [
+        ("tree/mod.rs", include_str!("../tree/mod.rs")),
+        ("tree/hasher.rs", include_str!("../tree/hasher.rs")),
+        ("tree/light.rs", include_str!("../tree/light.rs")),
+        (
+            "tree/merkle_tree.rs",
+            include_str!("../tree/merkle_tree.rs"),
+        ),
+    ];
+    let forbidden = ["std::", "format!(", "println!(", "String", "Box<"];
+
+    for (file, source) in sources {
+        for (number, line) in code_lines(source) {
+            for token in forbidden {
+                assert!(
+                    !line.contains(token),
+                    "{}:{} contains `{}`: {}",
+                    file,
+                    number,
+                    token,
+                    line.trim()
+                );
+            }
+
+            let bare_vec = line.contains("vec![") && !line.contains("ink_prelude::vec![");
+            assert!(
+                !bare_vec,
+                "{}:{} uses `vec!` without ink_prelude: {}",
+                file,
+                number,
+                line.trim()
+            );
+        }
+    }
+}
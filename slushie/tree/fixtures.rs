@@ -0,0 +1,229 @@
+//! Deterministic golden dataset for cross-implementation testing
+//!
+//! Derives `FIXTURE_LEAVES` commitments from `FIXTURE_SEED`, inserts them into a
+//! `MerkleTree<FIXTURE_DEPTH, FIXTURE_ROOT_HISTORY_SIZE, Poseidon>` and renders
+//! every commitment, its leaf index, the root after its insertion and the Merkle
+//! paths for `FIXTURE_PATH_LEAVES` as JSON.
+//!
+//! The rendered file is checked in at `tests/fixtures/merkle_tree_poseidon_1024.json`.
+//! Regenerate it with `SLUSHIE_UPDATE_FIXTURES=1 cargo test fixture`.
+
+use dusk_bls12_381::BlsScalar;
+use ink_env::hash::{Blake2x256, CryptoHash};
+
+use super::hasher::{MerkleTreeHasher, Poseidon};
+use super::merkle_tree::MerkleTree;
+use crate::format::to_hex;
+
+/// Seed from which all fixture commitments are derived
+pub const FIXTURE_SEED: &[u8] = b"slushie-fixture";
+/// Depth of the fixture tree
+pub const FIXTURE_DEPTH: usize = 20;
+/// Roots history size of the fixture tree
+pub const FIXTURE_ROOT_HISTORY_SIZE: usize = 30;
+/// Number of commitments inserted into the fixture tree
+pub const FIXTURE_LEAVES: usize = 1024;
+/// Leaf indexes for which Merkle paths are exported
+pub const FIXTURE_PATH_LEAVES: [usize; 4] = [0, 1, 511, 1023];
+
+/// Derive the commitment for a leaf index
+///
+/// commitment = scalar::from(blake2x256(FIXTURE_SEED ++ index.to_be_bytes()))
+pub fn fixture_commitment(index: u32) -> [u8; 32] {
+    let mut hash = [0; 32];
+    Blake2x256::hash(&[FIXTURE_SEED, &index.to_be_bytes()].concat(), &mut hash);
+
+    Poseidon::scalar_to_bytes(BlsScalar::from_raw(Poseidon::bytes_to_u64(hash)))
+}
+
+/// Merkle path of a leaf: siblings from the leaf level up with the side of the
+/// node on every level (0 – node is the left child, 1 – node is the right child)
+pub struct FixturePath<const DEPTH: usize = FIXTURE_DEPTH> {
+    pub leaf_index: usize,
+    pub siblings: [[u8; 32]; DEPTH],
+    pub path_indices: [u8; DEPTH],
+}
+
+impl<const DEPTH: usize> FixturePath<DEPTH> {
+    /// Read the path of a leaf from the levels returned by `levels`
+    pub fn new(levels: &[Vec<[u8; 32]>], leaf_index: usize) -> Self {
+        let mut siblings = [[0; 32]; DEPTH];
+        let mut path_indices = [0; DEPTH];
+
+        for level in 0..DEPTH {
+            let index = leaf_index >> level;
+
+            siblings[level] = levels[level]
+                .get(index ^ 1)
+                .copied()
+                .unwrap_or(Poseidon::ZEROS[level]);
+            path_indices[level] = (index % 2) as u8;
+        }
+
+        Self {
+            leaf_index,
+            siblings,
+            path_indices,
+        }
+    }
+
+    /// Render the path as a JSON object indented by `indent` spaces
+    pub fn to_json(&self, indent: usize) -> String {
+        let pad = " ".repeat(indent);
+        let siblings: Vec<String> = self
+            .siblings
+            .iter()
+            .map(|sibling| format!("{}    \"{}\"", pad, to_hex(sibling)))
+            .collect();
+        let path_indices: Vec<String> = self.path_indices.iter().map(|i| i.to_string()).collect();
+
+        format!(
+            "{pad}{{\n{pad}  \"leaf_index\": {},\n{pad}  \"path_indices\": [{}],\n{pad}  \"siblings\": [\n{}\n{pad}  ]\n{pad}}}",
+            self.leaf_index,
+            path_indices.join(", "),
+            siblings.join(",\n"),
+            pad = pad
+        )
+    }
+}
+
+/// Non-empty nodes on every level of a `DEPTH` deep tree, starting from the leaves
+pub fn levels<const DEPTH: usize>(leaves: &[[u8; 32]]) -> Vec<Vec<[u8; 32]>> {
+    let mut levels = vec![leaves.to_vec()];
+
+    for level in 0..DEPTH {
+        let nodes = levels[level]
+            .chunks(2)
+            .map(|pair| {
+                let right = pair.get(1).copied().unwrap_or(Poseidon::ZEROS[level]);
+                Poseidon::hash_left_right(pair[0], right)
+            })
+            .collect();
+
+        levels.push(nodes);
+    }
+
+    levels
+}
+
+/// Generated fixture data
+pub struct Fixture {
+    pub commitments: Vec<[u8; 32]>,
+    pub roots: Vec<[u8; 32]>,
+    pub paths: Vec<FixturePath>,
+}
+
+impl Fixture {
+    /// Generate the fixture by inserting all commitments into the tree
+    pub fn generate() -> Self {
+        let mut tree =
+            MerkleTree::<FIXTURE_DEPTH, FIXTURE_ROOT_HISTORY_SIZE, Poseidon>::new().unwrap();
+
+        let commitments: Vec<[u8; 32]> =
+            (0..FIXTURE_LEAVES as u32).map(fixture_commitment).collect();
+
+        let roots = commitments
+            .iter()
+            .map(|commitment| {
+                tree.insert(*commitment).unwrap();
+                tree.get_last_root()
+            })
+            .collect();
+
+        let levels = levels::<FIXTURE_DEPTH>(&commitments);
+
+        let paths = FIXTURE_PATH_LEAVES
+            .iter()
+            .map(|&leaf_index| FixturePath::new(&levels, leaf_index))
+            .collect();
+
+        Self {
+            commitments,
+            roots,
+            paths,
+        }
+    }
+
+    /// Render the fixture as JSON
+    pub fn to_json(&self) -> String {
+        let leaves: Vec<String> = self
+            .commitments
+            .iter()
+            .zip(&self.roots)
+            .enumerate()
+            .map(|(index, (commitment, root))| {
+                format!(
+                    "    {{ \"leaf_index\": {}, \"commitment\": \"{}\", \"root\": \"{}\" }}",
+                    index,
+                    to_hex(commitment),
+                    to_hex(root)
+                )
+            })
+            .collect();
+
+        let paths: Vec<String> = self.paths.iter().map(|path| path.to_json(4)).collect();
+
+        format!(
+            "{{\n  \"hasher\": \"poseidon\",\n  \"seed\": \"{}\",\n  \"depth\": {},\n  \"root_history_size\": {},\n  \"leaves\": [\n{}\n  ],\n  \"paths\": [\n{}\n  ]\n}}\n",
+            String::from_utf8_lossy(FIXTURE_SEED),
+            FIXTURE_DEPTH,
+            FIXTURE_ROOT_HISTORY_SIZE,
+            leaves.join(",\n"),
+            paths.join(",\n")
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::OnceLock;
+
+    use super::*;
+
+    /// Fixture shared between the tests, generating it takes a while
+    fn fixture() -> &'static Fixture {
+        static FIXTURE: OnceLock<Fixture> = OnceLock::new();
+        FIXTURE.get_or_init(Fixture::generate)
+    }
+
+    const FIXTURE_PATH: &str = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/fixtures/merkle_tree_poseidon_1024.json"
+    );
+
+    #[test]
+    fn test_fixture_matches_checked_in_file() {
+        let generated = fixture().to_json();
+
+        if std::env::var("SLUSHIE_UPDATE_FIXTURES").is_ok() {
+            std::fs::write(FIXTURE_PATH, &generated).unwrap();
+        }
+
+        let checked_in = std::fs::read_to_string(FIXTURE_PATH).unwrap();
+
+        assert!(
+            generated == checked_in,
+            "fixture differs from {}, regenerate it with SLUSHIE_UPDATE_FIXTURES=1",
+            FIXTURE_PATH
+        );
+    }
+
+    #[test]
+    fn test_fixture_paths_lead_to_last_root() {
+        let fixture = fixture();
+        let last_root = *fixture.roots.last().unwrap();
+
+        for path in &fixture.paths {
+            let mut current = fixture.commitments[path.leaf_index];
+
+            for level in 0..FIXTURE_DEPTH {
+                current = match path.path_indices[level] {
+                    0 => Poseidon::hash_left_right(current, path.siblings[level]),
+                    _ => Poseidon::hash_left_right(path.siblings[level], current),
+                };
+            }
+
+            assert_eq!(current, last_root);
+        }
+    }
+}
@@ -0,0 +1,319 @@
+//! Light verification of deposit events from an untrusted RPC
+//!
+//! A client keeps a trusted `Checkpoint`, the merkle tree at some leaf count.
+//! Given the commitments of the `Deposited` events after it, the client replays
+//! them with the same insertion as the contract and compares the result with
+//! the deposit count (`get_stats`) and the root (`get_root_hash`) the contract
+//! reports. Reordered, dropped or forged events lead to a different count or
+//! root.
+
+#[cfg(any(test, feature = "hasher-blake"))]
+pub use super::hasher::Blake;
+pub use super::hasher::MerkleTreeHasher;
+#[cfg(feature = "hasher-poseidon")]
+pub use super::hasher::Poseidon;
+pub use super::merkle_tree::{MerkleTree, MerkleTreeError, DEFAULT_ROOT_HISTORY_SIZE, MAX_DEPTH};
+
+/// Checkpoint of a pool running this build of the contract
+pub type PoolCheckpoint =
+    Checkpoint<MAX_DEPTH, DEFAULT_ROOT_HISTORY_SIZE, crate::slushie::TreeHasher>;
+
+/// Reasons the events don't match the reported state
+#[derive(Debug, PartialEq)]
+pub enum LightError {
+    /// The checkpoint isn't an encoded checkpoint of this shape, or its
+    /// count and root don't match its tree
+    InvalidCheckpoint,
+    /// The checkpoint plus the events don't add up to the reported count
+    CountMismatch { expected: u64, actual: u64 },
+    /// The replayed events lead to a different root
+    RootMismatch,
+    /// The events don't fit into the tree
+    MerkleTree(MerkleTreeError),
+}
+
+/// Merkle tree at a trusted deposit count, with its count and last root
+///
+/// Encoded as the count, the root and the SCALE encoded tree. Decoding fails
+/// unless the count and the root are the tree's, so a stored checkpoint can be
+/// compared with the contract without decoding the tree by hand.
+#[derive(scale::Encode, PartialEq)]
+#[cfg_attr(feature = "std", derive(Debug))]
+pub struct Checkpoint<const DEPTH: usize, const ROOT_HISTORY_SIZE: usize, Hash: MerkleTreeHasher> {
+    next_index: u64,
+    root: Hash::Output,
+    tree: MerkleTree<DEPTH, ROOT_HISTORY_SIZE, Hash>,
+}
+
+impl<const DEPTH: usize, const ROOT_HISTORY_SIZE: usize, Hash: MerkleTreeHasher>
+    Checkpoint<DEPTH, ROOT_HISTORY_SIZE, Hash>
+{
+    /// Checkpoint of the tree at its current count
+    pub fn new(tree: MerkleTree<DEPTH, ROOT_HISTORY_SIZE, Hash>) -> Self {
+        Self {
+            next_index: tree.next_index,
+            root: tree.get_last_root(),
+            tree,
+        }
+    }
+
+    /// Decode a checkpoint which must use up all of the bytes
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, LightError> {
+        <Self as scale::DecodeAll>::decode_all(&mut &bytes[..])
+            .map_err(|_| LightError::InvalidCheckpoint)
+    }
+
+    /// Number of deposits in the tree
+    pub fn next_index(&self) -> u64 {
+        self.next_index
+    }
+
+    /// Last root of the tree
+    pub fn root(&self) -> Hash::Output {
+        self.root
+    }
+
+    /// Tree at the count
+    pub fn tree(&self) -> &MerkleTree<DEPTH, ROOT_HISTORY_SIZE, Hash> {
+        &self.tree
+    }
+
+    /// Tree at the count, to insert more commitments into
+    pub fn into_tree(self) -> MerkleTree<DEPTH, ROOT_HISTORY_SIZE, Hash> {
+        self.tree
+    }
+}
+
+impl<const DEPTH: usize, const ROOT_HISTORY_SIZE: usize, Hash: MerkleTreeHasher> scale::Decode
+    for Checkpoint<DEPTH, ROOT_HISTORY_SIZE, Hash>
+{
+    fn decode<I: scale::Input>(input: &mut I) -> Result<Self, scale::Error> {
+        let next_index = u64::decode(input)?;
+        let root = Hash::Output::decode(input)?;
+        let tree = MerkleTree::decode(input)?;
+
+        if next_index != tree.next_index || root != tree.get_last_root() {
+            return Err("checkpoint count or root doesn't match its tree".into());
+        }
+
+        Ok(Self {
+            next_index,
+            root,
+            tree,
+        })
+    }
+}
+
+/// Replay `commitments` on top of `checkpoint` and check them against the
+/// deposit count and the last root reported by the contract
+///
+/// Returns the checkpoint after the events, the next trusted one
+pub fn verify_deposits<
+    const DEPTH: usize,
+    const ROOT_HISTORY_SIZE: usize,
+    Hash: MerkleTreeHasher,
+>(
+    checkpoint: Checkpoint<DEPTH, ROOT_HISTORY_SIZE, Hash>,
+    commitments: &[Hash::Output],
+    reported_count: u64,
+    reported_root: Hash::Output,
+) -> Result<Checkpoint<DEPTH, ROOT_HISTORY_SIZE, Hash>, LightError> {
+    // the checkpoint is trusted, it was taken after a previous verification
+    // or from a full node
+    let mut tree = checkpoint.into_tree();
+
+    let actual = tree.next_index + commitments.len() as u64;
+    if actual != reported_count {
+        return Err(LightError::CountMismatch {
+            expected: reported_count,
+            actual,
+        });
+    }
+
+    for commitment in commitments {
+        tree.insert(*commitment).map_err(LightError::MerkleTree)?;
+    }
+
+    if tree.get_last_root() != reported_root {
+        return Err(LightError::RootMismatch);
+    }
+
+    Ok(Checkpoint::new(tree))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use scale::Encode;
+
+    const TEST_DEPTH: usize = 8;
+
+    type TestTree = MerkleTree<TEST_DEPTH, 30, Blake>;
+    type TestCheckpoint = Checkpoint<TEST_DEPTH, 30, Blake>;
+
+    fn commitments(count: u8) -> Vec<[u8; 32]> {
+        (1..=count).map(|i| [i; 32]).collect()
+    }
+
+    /// Encoded checkpoint after `checkpointed` commitments and the state
+    /// reported by a contract which holds all of them
+    fn setup(checkpointed: usize, all: &[[u8; 32]]) -> (Vec<u8>, u64, [u8; 32]) {
+        let mut checkpoint = TestTree::new().unwrap();
+        for commitment in &all[..checkpointed] {
+            checkpoint.insert(*commitment).unwrap();
+        }
+
+        let mut tree = TestTree::new().unwrap();
+        for commitment in all {
+            tree.insert(*commitment).unwrap();
+        }
+
+        (
+            TestCheckpoint::new(checkpoint).encode(),
+            all.len() as u64,
+            tree.get_last_root(),
+        )
+    }
+
+    fn verify(
+        checkpoint: &[u8],
+        commitments: &[[u8; 32]],
+        count: u64,
+        root: [u8; 32],
+    ) -> Result<TestCheckpoint, LightError> {
+        verify_deposits(
+            TestCheckpoint::from_bytes(checkpoint)?,
+            commitments,
+            count,
+            root,
+        )
+    }
+
+    #[test]
+    fn test_verify_deposits() {
+        let all = commitments(10);
+        let (checkpoint, count, root) = setup(4, &all);
+
+        let next = verify(&checkpoint, &all[4..], count, root).unwrap();
+        assert_eq!(next.next_index(), 10);
+        assert_eq!(next.root(), root);
+        assert_eq!(next.tree().get_last_root(), root);
+
+        // the result is the next checkpoint
+        let more = commitments(12);
+        let (_, count, root) = setup(0, &more);
+        let next = verify(&next.encode(), &more[10..], count, root);
+        assert!(next.is_ok());
+    }
+
+    #[test]
+    fn test_verify_deposits_detects_reordered_events() {
+        let all = commitments(10);
+        let (checkpoint, count, root) = setup(4, &all);
+
+        let mut events = all[4..].to_vec();
+        events.swap(1, 2);
+
+        assert_eq!(
+            verify(&checkpoint, &events, count, root).err(),
+            Some(LightError::RootMismatch)
+        );
+    }
+
+    #[test]
+    fn test_verify_deposits_detects_dropped_events() {
+        let all = commitments(10);
+        let (checkpoint, count, root) = setup(4, &all);
+
+        let mut events = all[4..].to_vec();
+        events.remove(3);
+
+        assert_eq!(
+            verify(&checkpoint, &events, count, root).err(),
+            Some(LightError::CountMismatch {
+                expected: 10,
+                actual: 9
+            })
+        );
+    }
+
+    #[test]
+    fn test_verify_deposits_detects_forged_events() {
+        let all = commitments(10);
+        let (checkpoint, count, root) = setup(4, &all);
+
+        let mut events = all[4..].to_vec();
+        events[0] = [0xff; 32];
+
+        assert_eq!(
+            verify(&checkpoint, &events, count, root).err(),
+            Some(LightError::RootMismatch)
+        );
+
+        // a forged event in place of a real one keeps the count
+        events.pop();
+        events.push([0xfe; 32]);
+        assert_eq!(
+            verify(&checkpoint, &events, count, root).err(),
+            Some(LightError::RootMismatch)
+        );
+    }
+
+    #[test]
+    fn test_verify_deposits_rejects_invalid_checkpoint() {
+        let all = commitments(10);
+        let (checkpoint, count, root) = setup(4, &all);
+
+        assert_eq!(
+            verify(&checkpoint[1..], &all[4..], count, root).err(),
+            Some(LightError::InvalidCheckpoint)
+        );
+    }
+
+    #[test]
+    fn test_verify_deposits_rejects_trailing_bytes() {
+        let all = commitments(10);
+        let (mut checkpoint, count, root) = setup(4, &all);
+        checkpoint.push(0);
+
+        assert_eq!(
+            verify(&checkpoint, &all[4..], count, root).err(),
+            Some(LightError::InvalidCheckpoint)
+        );
+    }
+
+    #[test]
+    fn test_checkpoint_rejects_inconsistent_header() {
+        let all = commitments(10);
+        let (checkpoint, _, _) = setup(4, &all);
+
+        // the count is the first field, the root follows it
+        let mut wrong_count = checkpoint.clone();
+        wrong_count[0] += 1;
+        assert_eq!(
+            TestCheckpoint::from_bytes(&wrong_count).err(),
+            Some(LightError::InvalidCheckpoint)
+        );
+
+        let mut wrong_root = checkpoint;
+        wrong_root[8] ^= 1;
+        assert_eq!(
+            TestCheckpoint::from_bytes(&wrong_root).err(),
+            Some(LightError::InvalidCheckpoint)
+        );
+    }
+
+    #[test]
+    fn test_checkpoint_roundtrip() {
+        let all = commitments(5);
+        let mut tree = TestTree::new().unwrap();
+        for commitment in &all {
+            tree.insert(*commitment).unwrap();
+        }
+        let checkpoint = TestCheckpoint::new(tree);
+        assert_eq!(checkpoint.next_index(), 5);
+
+        let decoded = TestCheckpoint::from_bytes(&checkpoint.encode()).unwrap();
+        assert_eq!(decoded, checkpoint);
+    }
+}
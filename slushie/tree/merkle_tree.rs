@@ -1,22 +1,25 @@
+use ink_prelude::vec::Vec;
 #[cfg(feature = "std")]
 use ink_primitives::KeyPtr;
 #[cfg(feature = "std")]
 use ink_storage::traits::{ExtKeyPtr, StorageLayout};
 use ink_storage::traits::{PackedLayout, SpreadAllocate, SpreadLayout};
 
+use crate::math::CheckedMath;
+
 /// Merkle tree maximum depth
-pub const MAX_DEPTH: usize = 32;
+pub use crate::protocol_constants::MAX_DEPTH;
 /// Merkle tree history size
 pub const DEFAULT_ROOT_HISTORY_SIZE: usize = 100;
 
+/// Level, left input, right input and hash of every level of an insertion
+#[cfg(feature = "std")]
+pub type InsertTrace<Output> = Vec<(usize, Output, Output, Output)>;
+
 ///Merkle tree with history for storing commitments in it
 #[derive(scale::Encode, scale::Decode, PackedLayout, SpreadLayout, SpreadAllocate, PartialEq)]
 #[cfg_attr(feature = "std", derive(Debug, StorageLayout))]
-pub(crate) struct MerkleTree<
-    const DEPTH: usize,
-    const ROOT_HISTORY_SIZE: usize,
-    Hash: MerkleTreeHasher,
-> {
+pub struct MerkleTree<const DEPTH: usize, const ROOT_HISTORY_SIZE: usize, Hash: MerkleTreeHasher> {
     ///Current root index in the history
     pub current_root_index: u64,
     /// Next leaf index
@@ -31,6 +34,7 @@ impl<const DEPTH: usize, const ROOT_HISTORY_SIZE: usize, Hash: MerkleTreeHasher>
     MerkleTree<DEPTH, ROOT_HISTORY_SIZE, Hash>
 {
     ///Create merkle tree
+    // off-chain the depth is checked at runtime, the contract uses `new_checked`
     pub fn new() -> Result<Self, MerkleTreeError> {
         if DEPTH > MAX_DEPTH {
             return Err(MerkleTreeError::DepthTooLong);
@@ -40,49 +44,119 @@ impl<const DEPTH: usize, const ROOT_HISTORY_SIZE: usize, Hash: MerkleTreeHasher>
             return Err(MerkleTreeError::DepthIsZero);
         }
 
+        Ok(Self::empty())
+    }
+
+    /// Fails to compile if `DEPTH` isn't in 1..=MAX_DEPTH
+    const VALID_DEPTH: () = assert!(
+        DEPTH > 0 && DEPTH <= MAX_DEPTH,
+        "merkle tree depth should be in range 1..=MAX_DEPTH"
+    );
+
+    /// Create merkle tree whose depth is checked at compile time
+    ///
+    /// Unlike `new` this can't fail, the contract constructor uses it
+    pub fn new_checked() -> Self {
+        #[allow(clippy::let_unit_value)]
+        let () = Self::VALID_DEPTH;
+
+        Self::empty()
+    }
+
+    /// Empty tree, the caller makes sure `DEPTH` is in 1..=MAX_DEPTH
+    fn empty() -> Self {
         let roots = Array([Hash::ZEROS[DEPTH - 1]; ROOT_HISTORY_SIZE]);
 
         let mut filled_subtrees: Array<Hash::Output, DEPTH> = Default::default();
         filled_subtrees.0.copy_from_slice(&Hash::ZEROS[0..DEPTH]);
 
-        Ok(Self {
+        Self {
             current_root_index: 0,
             next_index: 0,
             filled_subtrees,
             roots,
-        })
+        }
     }
 
     /// Get last root hash
     pub fn get_last_root(&self) -> Hash::Output {
+        // current_root_index is always taken modulo ROOT_HISTORY_SIZE
         self.roots.0[self.current_root_index as usize]
     }
 
     /// Check existing provided root in roots history
     pub fn is_known_root(&self, root: Hash::Output) -> bool {
+        self.root_age(root).is_some()
+    }
+
+    /// Number of insertions since the root was the last one, 0 for the last root
+    ///
+    /// Returns None if the root isn't in the roots history
+    pub fn root_age(&self, root: Hash::Output) -> Option<u64> {
         if root == Default::default() {
-            return false;
+            return None;
         }
 
         let root_history_size_u64 = ROOT_HISTORY_SIZE as u64;
 
-        for i in 0..root_history_size_u64 {
+        // current_root_index < ROOT_HISTORY_SIZE and i < ROOT_HISTORY_SIZE,
+        // so the subtraction can't underflow
+        (0..root_history_size_u64).find(|i| {
             let current_index = ((root_history_size_u64 + self.current_root_index - i)
                 % root_history_size_u64) as usize;
 
-            if root == self.roots.0[current_index] {
-                return true;
-            }
-        }
+            self.roots.0.get(current_index) == Some(&root)
+        })
+    }
+
+    /// Replace the last root, for the contract's test hooks
+    #[cfg(test)]
+    pub fn force_last_root(&mut self, root: Hash::Output) {
+        self.roots.0[self.current_root_index as usize] = root;
+    }
 
-        false
+    /// Check if there is no space for new leaves
+    pub fn is_full(&self) -> bool {
+        // the capacity only overflows u64 for depths the tree can't be created with
+        match 2u64.checked_pow(DEPTH as u32) {
+            Some(capacity) => self.next_index >= capacity,
+            None => false,
+        }
     }
 
     ///Insert leaf in the merkle tree
     pub fn insert(&mut self, leaf: Hash::Output) -> Result<usize, MerkleTreeError> {
+        self.insert_with(leaf, |_, _, _, _| {})
+    }
+
+    /// Insert leaf in the merkle tree and return every hashed node on its path
+    ///
+    /// For each level from the leaves up: the level, the left input, the
+    /// right input and the resulting hash. Runs the same code as `insert`.
+    #[cfg(feature = "std")]
+    // off-chain debugging helper, not used by the contract
+    pub fn insert_traced(
+        &mut self,
+        leaf: Hash::Output,
+    ) -> (Result<usize, MerkleTreeError>, InsertTrace<Hash::Output>) {
+        let mut trace = Vec::with_capacity(DEPTH);
+        let result = self.insert_with(leaf, |level, left, right, hash| {
+            trace.push((level, left, right, hash))
+        });
+
+        (result, trace)
+    }
+
+    /// Insertion shared by `insert` and `insert_traced`, `on_level` is called
+    /// with the level, the left and right inputs and the hash of every level
+    fn insert_with(
+        &mut self,
+        leaf: Hash::Output,
+        mut on_level: impl FnMut(usize, Hash::Output, Hash::Output, Hash::Output),
+    ) -> Result<usize, MerkleTreeError> {
         let next_index = self.next_index as usize;
 
-        if self.next_index == 2u64.pow(DEPTH as u32) {
+        if self.is_full() {
             return Err(MerkleTreeError::MerkleTreeIsFull);
         }
 
@@ -90,37 +164,132 @@ impl<const DEPTH: usize, const ROOT_HISTORY_SIZE: usize, Hash: MerkleTreeHasher>
         let mut current_index = next_index;
         let mut current_hash = leaf;
 
-        for i in 0..DEPTH {
+        // DEPTH <= MAX_DEPTH, so the zip covers every level of the tree
+        let levels = self.filled_subtrees.0.iter_mut().zip(Hash::ZEROS);
+
+        for (i, (filled_subtree, zero)) in levels.enumerate() {
             let left;
             let right;
 
-            if current_index % 2 == 0 {
-                right = Hash::ZEROS[i];
+            if current_index.is_multiple_of(2) {
+                right = zero;
                 left = current_hash;
 
-                self.filled_subtrees.0[i] = current_hash;
+                *filled_subtree = current_hash;
             } else {
-                left = self.filled_subtrees.0[i];
+                left = *filled_subtree;
                 right = current_hash;
             }
 
             current_hash = Hash::hash_left_right(left, right);
+            on_level(i, left, right, current_hash);
             current_index /= 2;
         }
 
-        self.current_root_index = (self.current_root_index + 1) % root_history_size_u64;
+        self.current_root_index = self
+            .current_root_index
+            .checked_add_or(1, MerkleTreeError::MerkleTreeIsFull)?
+            % root_history_size_u64;
 
+        // in bounds, the index was just taken modulo ROOT_HISTORY_SIZE
         self.roots.0[self.current_root_index as usize] = current_hash;
 
-        self.next_index += 1;
+        self.next_index = self
+            .next_index
+            .checked_add_or(1, MerkleTreeError::MerkleTreeIsFull)?;
+
+        #[cfg(any(test, feature = "dev-assertions"))]
+        self.assert_invariants(leaf);
 
         Ok(next_index)
     }
+
+    /// Panic if the tree state is inconsistent after inserting `last_leaf`
+    ///
+    /// Recomputes the last root along the path of the last leaf from
+    /// `filled_subtrees` and `next_index`, every left node on the path must be
+    /// the filled subtree of its level. Compiled in tests and with the
+    /// `dev-assertions` feature only.
+    #[cfg(any(test, feature = "dev-assertions"))]
+    pub fn assert_invariants(&self, last_leaf: Hash::Output) {
+        assert!(
+            self.next_index <= 2u64.pow(DEPTH as u32),
+            "next_index exceeds the tree capacity"
+        );
+        assert!(
+            (self.current_root_index as usize) < ROOT_HISTORY_SIZE,
+            "current_root_index exceeds the roots history"
+        );
+
+        let mut index = self.next_index - 1;
+        let mut current_hash = last_leaf;
+
+        for i in 0..DEPTH {
+            current_hash = if index.is_multiple_of(2) {
+                assert!(
+                    self.filled_subtrees.0[i] == current_hash,
+                    "filled subtree doesn't match the last leaf path"
+                );
+                Hash::hash_left_right(current_hash, Hash::ZEROS[i])
+            } else {
+                Hash::hash_left_right(self.filled_subtrees.0[i], current_hash)
+            };
+            index /= 2;
+        }
+
+        assert!(
+            current_hash == self.get_last_root(),
+            "last root doesn't match the filled subtrees"
+        );
+    }
+
+    /// Check that the proof leads from the leaf to a known root
+    ///
+    /// The proof must have exactly DEPTH siblings and point to an already inserted leaf
+    pub fn verify_proof(&self, leaf: Hash::Output, proof: &MerkleProof<Hash::Output>) -> bool {
+        if proof.siblings.len() != DEPTH || proof.leaf_index >= self.next_index {
+            return false;
+        }
+
+        self.is_known_root(proof.compute_root::<Hash>(leaf))
+    }
+}
+
+///Merkle proof of the leaf inclusion in the merkle tree
+#[derive(scale::Encode, scale::Decode, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "std", derive(Debug, scale_info::TypeInfo))]
+pub struct MerkleProof<Output> {
+    ///Index of the leaf in the merkle tree
+    pub leaf_index: u64,
+    ///Siblings of the nodes on the path from the leaf to the root, starting from the leaf level
+    pub siblings: Vec<Output>,
+}
+
+impl<Output: Copy> MerkleProof<Output> {
+    ///Compute the root for the leaf from the path
+    ///
+    ///On every level the node is the left child if the corresponding bit of `leaf_index` is 0
+    pub fn compute_root<Hash: MerkleTreeHasher<Output = Output>>(&self, leaf: Output) -> Output {
+        let mut current_index = self.leaf_index;
+        let mut current_hash = leaf;
+
+        for sibling in &self.siblings {
+            current_hash = if current_index.is_multiple_of(2) {
+                Hash::hash_left_right(current_hash, *sibling)
+            } else {
+                Hash::hash_left_right(*sibling, current_hash)
+            };
+
+            current_index /= 2;
+        }
+
+        current_hash
+    }
 }
 
 ///Enum with contain merkle tree errors
 #[derive(Debug, PartialEq)]
-pub(crate) enum MerkleTreeError {
+pub enum MerkleTreeError {
     ///Merkle tree is full
     MerkleTreeIsFull,
     ///Depth should be in range 1..MAX_DEPTH
@@ -171,6 +340,100 @@ mod tests {
     use super::*;
     const TEST_MAX_DEPTH: usize = 10;
 
+    /// Build the proof for the leaf by computing every level of the tree
+    fn build_proof<const DEPTH: usize, Hash: MerkleTreeHasher>(
+        leaves: &[Hash::Output],
+        leaf_index: usize,
+    ) -> MerkleProof<Hash::Output> {
+        let mut level_nodes = leaves.to_vec();
+        let mut siblings = Vec::new();
+
+        for level in 0..DEPTH {
+            let index = leaf_index >> level;
+            siblings.push(
+                level_nodes
+                    .get(index ^ 1)
+                    .copied()
+                    .unwrap_or(Hash::ZEROS[level]),
+            );
+
+            level_nodes = level_nodes
+                .chunks(2)
+                .map(|pair| {
+                    Hash::hash_left_right(
+                        pair[0],
+                        pair.get(1).copied().unwrap_or(Hash::ZEROS[level]),
+                    )
+                })
+                .collect();
+        }
+
+        MerkleProof {
+            leaf_index: leaf_index as u64,
+            siblings,
+        }
+    }
+
+    #[test]
+    fn test_compute_root() {
+        let mut tree = MerkleTree::<TEST_MAX_DEPTH, 30, Blake>::new().unwrap();
+        let leaves: Vec<[u8; 32]> = (0..7).map(|i| [i as u8 + 1; 32]).collect();
+
+        for leaf in &leaves {
+            tree.insert(*leaf).unwrap();
+        }
+
+        for (i, leaf) in leaves.iter().enumerate() {
+            let proof = build_proof::<TEST_MAX_DEPTH, Blake>(&leaves, i);
+
+            assert_eq!(proof.compute_root::<Blake>(*leaf), tree.get_last_root());
+            assert!(tree.verify_proof(*leaf, &proof));
+        }
+    }
+
+    #[test]
+    fn test_verify_proof_with_historical_root() {
+        let mut tree = MerkleTree::<TEST_MAX_DEPTH, 30, Blake>::new().unwrap();
+        let leaves: Vec<[u8; 32]> = (0..3).map(|i| [i as u8 + 1; 32]).collect();
+
+        for leaf in &leaves {
+            tree.insert(*leaf).unwrap();
+        }
+
+        let proof = build_proof::<TEST_MAX_DEPTH, Blake>(&leaves[..2], 1);
+        tree.insert([9; 32]).unwrap();
+
+        assert!(tree.verify_proof(leaves[1], &proof));
+    }
+
+    #[test]
+    fn test_verify_proof_fails() {
+        let mut tree = MerkleTree::<TEST_MAX_DEPTH, 30, Blake>::new().unwrap();
+        let leaves: Vec<[u8; 32]> = (0..4).map(|i| [i as u8 + 1; 32]).collect();
+
+        for leaf in &leaves {
+            tree.insert(*leaf).unwrap();
+        }
+
+        let proof = build_proof::<TEST_MAX_DEPTH, Blake>(&leaves, 2);
+        assert!(!tree.verify_proof([7; 32], &proof));
+
+        let mut tampered = proof.clone();
+        tampered.siblings[3] = [7; 32];
+        assert!(!tree.verify_proof(leaves[2], &tampered));
+
+        let mut wrong_index = proof.clone();
+        wrong_index.leaf_index = 3;
+        assert!(!tree.verify_proof(leaves[2], &wrong_index));
+
+        let mut too_short = proof.clone();
+        too_short.siblings.pop();
+        assert!(!tree.verify_proof(leaves[2], &too_short));
+
+        let zero_leaf_proof = build_proof::<TEST_MAX_DEPTH, Blake>(&leaves, 4);
+        assert!(!tree.verify_proof(Blake::ZEROS[0], &zero_leaf_proof));
+    }
+
     #[test]
     fn test_get_zero_root() {
         let tree = MerkleTree::<TEST_MAX_DEPTH, 30, Blake>::new().unwrap();
@@ -218,6 +481,17 @@ mod tests {
         assert_eq!(err, Err(MerkleTreeError::MerkleTreeIsFull));
     }
 
+    #[test]
+    fn test_error_when_next_index_at_max() {
+        let mut tree = MerkleTree::<3, 30, Blake>::new().unwrap();
+        tree.next_index = u64::MAX;
+
+        let err = tree.insert([6; 32]);
+
+        assert_eq!(err, Err(MerkleTreeError::MerkleTreeIsFull));
+        assert_eq!(tree.next_index, u64::MAX);
+    }
+
     #[test]
     fn test_error_when_tree_depth_too_long() {
         const MAX_DEPTH_PLUS_1: usize = MAX_DEPTH + 1;
@@ -234,6 +508,13 @@ mod tests {
         assert_eq!(tree, Err(MerkleTreeError::DepthIsZero));
     }
 
+    #[test]
+    fn test_new_checked_matches_new() {
+        let tree = MerkleTree::<TEST_MAX_DEPTH, 30, Blake>::new_checked();
+
+        assert_eq!(Ok(tree), MerkleTree::<TEST_MAX_DEPTH, 30, Blake>::new());
+    }
+
     #[test]
     fn test_is_known_root() {
         let mut tree = MerkleTree::<TEST_MAX_DEPTH, 30, Blake>::new().unwrap();
@@ -252,6 +533,30 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_root_age() {
+        let mut tree = MerkleTree::<TEST_MAX_DEPTH, 30, Blake>::new().unwrap();
+        let initial_root = tree.get_last_root();
+
+        assert_eq!(tree.root_age(initial_root), Some(0));
+        assert_eq!(tree.root_age([0; 32]), None);
+
+        let mut roots = vec![initial_root];
+        for i in 0..45 {
+            tree.insert([i as u8 + 1; 32]).unwrap();
+            roots.push(tree.get_last_root());
+
+            // every root in the history, across the wraparound of `roots`
+            for (age, root) in roots.iter().rev().take(30).enumerate() {
+                assert_eq!(tree.root_age(*root), Some(age as u64));
+            }
+        }
+
+        for root in roots.iter().rev().skip(30) {
+            assert_eq!(tree.root_age(*root), None);
+        }
+    }
+
     #[test]
     fn test_roots_field() {
         let mut tree = MerkleTree::<TEST_MAX_DEPTH, 30, Blake>::new().unwrap();
@@ -416,4 +721,101 @@ mod tests {
             result = dusk_poseidon::sponge::hash(&[result, result]);
         }
     }
+
+    /// Parse a table of zero elements checked in as one hex string per line
+    fn checked_in_zeros(table: &str) -> Vec<[u8; 32]> {
+        table
+            .lines()
+            .map(|line| {
+                let mut zero = [0; 32];
+                for (byte, hex) in zero.iter_mut().zip(line.as_bytes().chunks_exact(2)) {
+                    *byte = u8::from_str_radix(core::str::from_utf8(hex).unwrap(), 16).unwrap();
+                }
+                zero
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_generated_zeros_match_checked_in_blake() {
+        let checked_in = checked_in_zeros(include_str!("../tests/fixtures/zeros_blake.txt"));
+        assert_eq!(&Blake::ZEROS[..], &checked_in[..]);
+    }
+
+    #[test]
+    fn test_generated_zeros_match_checked_in_poseidon() {
+        let checked_in = checked_in_zeros(include_str!("../tests/fixtures/zeros_poseidon.txt"));
+        assert_eq!(&Poseidon::ZEROS[..], &checked_in[..]);
+    }
+
+    #[test]
+    #[should_panic(expected = "filled subtree doesn't match the last leaf path")]
+    fn test_invariants_catch_corrupted_filled_subtrees() {
+        let mut tree = MerkleTree::<TEST_MAX_DEPTH, 30, Blake>::new().unwrap();
+        let leaf = [1; 32];
+        tree.insert(leaf).unwrap();
+
+        tree.filled_subtrees.0[0] = [2; 32];
+
+        tree.assert_invariants(leaf);
+    }
+
+    #[test]
+    #[should_panic(expected = "last root doesn't match the filled subtrees")]
+    fn test_invariants_catch_corrupted_root() {
+        let mut tree = MerkleTree::<TEST_MAX_DEPTH, 30, Blake>::new().unwrap();
+        let leaf = [1; 32];
+        tree.insert(leaf).unwrap();
+        tree.insert(leaf).unwrap();
+
+        tree.roots.0[tree.current_root_index as usize] = [2; 32];
+
+        tree.assert_invariants(leaf);
+    }
+
+    #[test]
+    #[should_panic(expected = "current_root_index exceeds the roots history")]
+    fn test_invariants_catch_corrupted_root_index() {
+        let mut tree = MerkleTree::<TEST_MAX_DEPTH, 30, Blake>::new().unwrap();
+        let leaf = [1; 32];
+        tree.insert(leaf).unwrap();
+
+        tree.current_root_index = 30;
+
+        tree.assert_invariants(leaf);
+    }
+
+    #[test]
+    fn test_insert_traced() {
+        let mut tree = MerkleTree::<TEST_MAX_DEPTH, 30, Blake>::new().unwrap();
+        let mut traced_tree = MerkleTree::<TEST_MAX_DEPTH, 30, Blake>::new().unwrap();
+
+        for i in 0..5u8 {
+            let leaf = [i + 1; 32];
+            let (result, trace) = traced_tree.insert_traced(leaf);
+
+            assert_eq!(result, tree.insert(leaf));
+            assert_eq!(traced_tree, tree);
+
+            assert_eq!(trace.len(), TEST_MAX_DEPTH);
+            assert_eq!(trace[0].1, if i % 2 == 0 { leaf } else { [i; 32] });
+            for (level, (traced_level, left, right, hash)) in trace.iter().enumerate() {
+                assert_eq!(*traced_level, level);
+                assert_eq!(*hash, Blake::hash_left_right(*left, *right));
+            }
+            assert_eq!(trace[TEST_MAX_DEPTH - 1].3, tree.get_last_root());
+        }
+    }
+
+    #[test]
+    fn test_insert_traced_in_full_tree() {
+        let mut tree = MerkleTree::<1, 30, Blake>::new().unwrap();
+        tree.insert([1; 32]).unwrap();
+        tree.insert([2; 32]).unwrap();
+
+        let (result, trace) = tree.insert_traced([3; 32]);
+
+        assert_eq!(result, Err(MerkleTreeError::MerkleTreeIsFull));
+        assert!(trace.is_empty());
+    }
 }
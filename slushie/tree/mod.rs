@@ -1,2 +1,7 @@
+#[cfg(test)]
+pub(crate) mod fixtures;
 pub(crate) mod hasher;
+pub mod light;
 pub(crate) mod merkle_tree;
+#[cfg(test)]
+pub(crate) mod vectors;
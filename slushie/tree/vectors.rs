@@ -0,0 +1,185 @@
+//! Reference test vectors for third-party implementations
+//!
+//! A versioned bundle of Poseidon `hash_left_right` pairs, `hash_bytes` cases and
+//! a `VECTORS_LEAVES` leaf tree of the contract's depth with the root after every
+//! insertion and the Merkle paths for `VECTORS_PATH_LEAVES`. Leaves are derived
+//! by `fixture_commitment`. The version is `PROTOCOL_VERSION`.
+//!
+//! The rendered bundle is checked in at `tests/vectors/slushie_vectors.json`.
+//! Regenerate it with `SLUSHIE_UPDATE_FIXTURES=1 cargo test vectors`.
+
+use super::fixtures::{fixture_commitment, levels, FixturePath};
+use super::hasher::{MerkleTreeHasher, Poseidon};
+use super::merkle_tree::{MerkleTree, MAX_DEPTH};
+use crate::format::to_hex;
+use crate::protocol_constants::PROTOCOL_VERSION;
+
+/// Number of leaves inserted into the vectors tree
+pub const VECTORS_LEAVES: usize = 64;
+/// Leaf indexes for which Merkle paths are exported
+pub const VECTORS_PATH_LEAVES: [usize; 3] = [0, 31, 63];
+/// Input lengths of the `hash_bytes` cases, around the 31-byte chunk boundaries
+pub const VECTORS_HASH_BYTES_LENGTHS: [usize; 9] = [0, 1, 30, 31, 32, 61, 62, 63, 100];
+
+/// Generated test vectors
+pub struct Vectors {
+    pub hash_left_right: Vec<([u8; 32], [u8; 32], [u8; 32])>,
+    pub hash_bytes: Vec<(Vec<u8>, [u8; 32])>,
+    pub leaves: Vec<[u8; 32]>,
+    pub roots: Vec<[u8; 32]>,
+    pub paths: Vec<FixturePath<MAX_DEPTH>>,
+}
+
+impl Vectors {
+    /// Generate the vectors
+    pub fn generate() -> Self {
+        let zero = Poseidon::ZEROS[0];
+        let (first, second) = (fixture_commitment(0), fixture_commitment(1));
+
+        let hash_left_right = [
+            (zero, zero),
+            (first, second),
+            (second, first),
+            (first, zero),
+            (zero, first),
+        ]
+        .into_iter()
+        .map(|(left, right)| (left, right, Poseidon::hash_left_right(left, right)))
+        .collect();
+
+        let hash_bytes = VECTORS_HASH_BYTES_LENGTHS
+            .iter()
+            .map(|&length| {
+                let input: Vec<u8> = (0..length).map(|byte| byte as u8).collect();
+                let hash = Poseidon::hash_bytes(&input);
+                (input, hash)
+            })
+            .collect();
+
+        let mut tree = MerkleTree::<MAX_DEPTH, VECTORS_LEAVES, Poseidon>::new().unwrap();
+        let leaves: Vec<[u8; 32]> = (0..VECTORS_LEAVES as u32).map(fixture_commitment).collect();
+        let roots = leaves
+            .iter()
+            .map(|leaf| {
+                tree.insert(*leaf).unwrap();
+                tree.get_last_root()
+            })
+            .collect();
+
+        let levels = levels::<MAX_DEPTH>(&leaves);
+        let paths = VECTORS_PATH_LEAVES
+            .iter()
+            .map(|&leaf_index| FixturePath::new(&levels, leaf_index))
+            .collect();
+
+        Self {
+            hash_left_right,
+            hash_bytes,
+            leaves,
+            roots,
+            paths,
+        }
+    }
+
+    /// Render the vectors as JSON
+    pub fn to_json(&self) -> String {
+        let hash_left_right: Vec<String> = self
+            .hash_left_right
+            .iter()
+            .map(|(left, right, hash)| {
+                format!(
+                    "    {{ \"left\": \"{}\", \"right\": \"{}\", \"hash\": \"{}\" }}",
+                    to_hex(left),
+                    to_hex(right),
+                    to_hex(hash)
+                )
+            })
+            .collect();
+
+        let hash_bytes: Vec<String> = self
+            .hash_bytes
+            .iter()
+            .map(|(input, hash)| {
+                format!(
+                    "    {{ \"input\": \"{}\", \"hash\": \"{}\" }}",
+                    to_hex(input),
+                    to_hex(hash)
+                )
+            })
+            .collect();
+
+        let leaves: Vec<String> = self
+            .leaves
+            .iter()
+            .zip(&self.roots)
+            .enumerate()
+            .map(|(index, (leaf, root))| {
+                format!(
+                    "      {{ \"leaf_index\": {}, \"commitment\": \"{}\", \"root\": \"{}\" }}",
+                    index,
+                    to_hex(leaf),
+                    to_hex(root)
+                )
+            })
+            .collect();
+
+        let paths: Vec<String> = self.paths.iter().map(|path| path.to_json(6)).collect();
+
+        format!(
+            "{{\n  \"version\": {},\n  \"hasher\": \"poseidon\",\n  \"hash_left_right\": [\n{}\n  ],\n  \"hash_bytes\": [\n{}\n  ],\n  \"tree\": {{\n    \"depth\": {},\n    \"leaves\": [\n{}\n    ],\n    \"paths\": [\n{}\n    ]\n  }}\n}}\n",
+            PROTOCOL_VERSION,
+            hash_left_right.join(",\n"),
+            hash_bytes.join(",\n"),
+            MAX_DEPTH,
+            leaves.join(",\n"),
+            paths.join(",\n")
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VECTORS_PATH: &str = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/vectors/slushie_vectors.json"
+    );
+
+    #[test]
+    fn test_vectors_match_checked_in_file() {
+        let generated = Vectors::generate().to_json();
+
+        if std::env::var("SLUSHIE_UPDATE_FIXTURES").is_ok() {
+            std::fs::create_dir_all(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/vectors")).unwrap();
+            std::fs::write(VECTORS_PATH, &generated).unwrap();
+        }
+
+        let checked_in = std::fs::read_to_string(VECTORS_PATH).unwrap();
+
+        assert!(
+            generated == checked_in,
+            "vectors differ from {}, regenerate them with SLUSHIE_UPDATE_FIXTURES=1",
+            VECTORS_PATH
+        );
+    }
+
+    #[test]
+    fn test_vectors_paths_lead_to_last_root() {
+        let vectors = Vectors::generate();
+        let last_root = *vectors.roots.last().unwrap();
+
+        for path in &vectors.paths {
+            let mut current = vectors.leaves[path.leaf_index];
+
+            for level in 0..MAX_DEPTH {
+                current = match path.path_indices[level] {
+                    0 => Poseidon::hash_left_right(current, path.siblings[level]),
+                    _ => Poseidon::hash_left_right(path.siblings[level], current),
+                };
+            }
+
+            assert_eq!(current, last_root);
+        }
+    }
+}